@@ -0,0 +1,123 @@
+use std::{fs::File, io::{BufRead, BufReader, Read, Seek, SeekFrom}, ops::Range, path::Path};
+
+use crate::{count_lines, finish, process_chunk, schedule, Error, Options, Partial, Report};
+
+/// Aggregates a file by reading every chunk into its own buffer.
+///
+/// This is the fallback for files that can't be memory-mapped. With `options.window_size` set,
+/// every chunk is read in windows of that size instead of all at once.
+pub(crate) fn aggregate_buffered(path: &Path, length: usize, options: &Options) -> Result<Report, Error> {
+    let align = |position| align(path, length, position);
+    let header = header_length(path, options.skip_header)?;
+    let partials = schedule::run(header.min(length)..length, options, align, |thread_results: &mut Partial, range| process_range(path, range, options, thread_results))?;
+
+    Ok(finish(partials))
+}
+
+/// Reads and aggregates the lines in `range` of the file at `path`, positioning errors in the whole file.
+pub(crate) fn process_range(path: &Path, range: Range<usize>, options: &Options, thread_results: &mut Partial) -> Result<(), Error> {
+    let mut thread_file = File::open(path)?;
+    let offset = range.start;
+    let size = range.len();
+    thread_file.seek(SeekFrom::Start(offset as u64))?;
+
+    if let Some(window_size) = options.window_size {
+        return process_windowed(&mut thread_file, offset as u64, size, window_size.max(1), options, thread_results).map_err(|err| locate(err, path, offset));
+    }
+
+    let mut contents: Vec<u8> = vec![0_u8; size];
+    thread_file.read_exact(&mut contents)?;
+
+    let chunk = process_chunk(&contents, offset as u64, options).map_err(|err| locate(err.into(), path, offset))?;
+    thread_results.accumulate(chunk);
+    Ok(())
+}
+
+/// Processes the next `size` bytes of `file`, from byte `offset` on, in windows of `window_size` bytes.
+///
+/// A partial line at the end of a window is carried over to the next one, so memory use stays
+/// at about `window_size` plus the longest line.
+///
+/// Parse errors are positioned relative to the start of the range.
+fn process_windowed(file: &mut File, offset: u64, size: usize, window_size: usize, options: &Options, thread_results: &mut Partial) -> Result<(), Error> {
+    let mut buffer: Vec<u8> = Vec::with_capacity(window_size);
+    let mut remaining = size;
+    let mut processed = 0;
+    let mut lines = 0;
+    while remaining > 0 {
+        let carried = buffer.len();
+        let to_read = window_size.min(remaining);
+        buffer.resize(carried + to_read, 0);
+        file.read_exact(&mut buffer[carried..])?;
+        remaining -= to_read;
+
+        let complete = match remaining {
+            0 => buffer.len(),
+            _ => buffer.iter().rposition(|byte| *byte == b'\n').map_or(0, |newline| newline + 1),
+        };
+        let chunk = process_chunk(&buffer[..complete], offset + processed, options).map_err(|err| err.shifted(processed, lines))?;
+        lines += chunk.lines;
+        processed += complete as u64;
+        thread_results.accumulate(chunk);
+        buffer.drain(..complete);
+    }
+    Ok(())
+}
+
+/// Moves a parse error found in the range starting at `offset` to its position in the file.
+fn locate(err: Error, path: &Path, offset: usize) -> Error {
+    let Error::Parse(parse_error) = err else {
+        return err;
+    };
+    // only runs once per failed run, so reading the start of the file again is fine
+    let mut lines = 0;
+    let mut reader = BufReader::new(match File::open(path) {
+        Ok(file) => file.take(offset as u64),
+        Err(err) => return err.into(),
+    });
+    let mut buffer = vec![0_u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => lines += count_lines(&buffer[..read]),
+            Err(err) => return err.into(),
+        }
+    }
+    Error::Parse(parse_error.shifted(offset as u64, lines))
+}
+
+/// Returns the length of the first `lines` lines of the file at `path`.
+pub(crate) fn header_length(path: &Path, lines: usize) -> Result<usize, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = Vec::new();
+    let mut length = 0;
+    for _ in 0..lines {
+        line.clear();
+        match reader.read_until(b'\n', &mut line)? {
+            0 => break,
+            read => length += read,
+        }
+    }
+    Ok(length)
+}
+
+/// Returns the start of the first line that starts at or after `position`.
+pub(crate) fn align(path: &Path, length: usize, position: usize) -> Result<usize, Error> {
+    if position == 0 || position >= length {
+        return Ok(position.min(length));
+    }
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(position as u64 - 1))?;
+    let mut position = position - 1;
+    let mut buf = [0; 256];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            return Ok(length);
+        }
+        if let Some(newline) = buf[..read].iter().position(|byte| *byte == b'\n') {
+            return Ok((position + newline + 1).min(length));
+        }
+        position += read;
+    }
+}
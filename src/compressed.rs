@@ -0,0 +1,165 @@
+//! Reading gzip and zstd compressed input.
+//!
+//! Compressed input can't be split at arbitrary positions, so it is decoded on a single thread
+//! and cut into newline-aligned blocks for the stream workers. A zstd file made of several
+//! independent frames is the exception: every frame can be decoded on its own, so the frames are
+//! decoded and parsed in parallel, and the lines that span two frames are stitched together at
+//! the end.
+
+use std::{fs::File, io::{self, ErrorKind, Read, Seek}, ops::Range};
+
+use flate2::read::MultiGzDecoder;
+use zstd::zstd_safe;
+
+use crate::{aggregate_reader_report, count_lines, process_chunk, schedule, Chunking, Error, Options, ParseError, Partial, PartialResults, Report};
+
+/// A compression format recognized by its magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn detect(magic: &[u8]) -> Option<Compression> {
+        match magic {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Looks at the start of `file` and rewinds it.
+    pub(crate) fn sniff(file: &mut File) -> io::Result<Option<Compression>> {
+        let mut magic = Vec::with_capacity(4);
+        file.by_ref().take(4).read_to_end(&mut magic)?;
+        file.rewind()?;
+        Ok(Compression::detect(&magic))
+    }
+}
+
+/// Wraps `reader` in a decoder if it starts with the magic bytes of a compression format.
+pub(crate) fn decoder<'a, R: Read + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let mut magic = Vec::with_capacity(4);
+    reader.by_ref().take(4).read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    let reader = io::Cursor::new(magic).chain(reader);
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(reader)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(reader)?),
+        None => Box::new(reader),
+    })
+}
+
+/// A decoded frame, with the lines it shares with its neighbours kept aside.
+#[derive(Default)]
+struct Frame {
+    /// Decompressed size.
+    length: u64,
+    /// Number of newlines.
+    lines: u64,
+    /// Everything before the first newline, or the whole frame if it has none.
+    head: Vec<u8>,
+    /// Everything after the last newline.
+    tail: Vec<u8>,
+    /// Where the complete lines between head and tail start.
+    body_start: u64,
+    /// The first malformed line of the body in strict mode, positioned relative to the body.
+    error: Option<ParseError>,
+}
+
+/// The results of a worker decoding frames.
+#[derive(Default)]
+struct FrameResults {
+    stations: Partial,
+    frames: Vec<(usize, Frame)>,
+}
+
+/// Aggregates zstd compressed `data`, decoding its frames in parallel.
+pub(crate) fn aggregate_zstd(data: &[u8], options: &Options) -> Result<Report, Error> {
+    let frames = frames(data)?;
+    // header lines can only be told apart in order, and the positions of extreme readings need
+    // the decompressed length of every frame before them
+    if frames.len() < 2 || options.skip_header > 0 || options.extremes > 0 {
+        return aggregate_reader_report(data, options);
+    }
+
+    // every frame is a chunk of its own
+    let frame_options = Options { chunking: Chunking::Queue, chunk_size: 1, ..options.clone() };
+    let partials = schedule::run(0..frames.len(), &frame_options, Ok, |results: &mut FrameResults, range| {
+        for index in range {
+            let frame = decode_frame(&data[frames[index].clone()], options, &mut results.stations)?;
+            results.frames.push((index, frame));
+        }
+        Ok(())
+    })?;
+
+    // put the frames back in order and parse the lines that span them
+    let mut decoded: Vec<(usize, Frame)> = Vec::with_capacity(frames.len());
+    let mut stations = Vec::with_capacity(partials.len());
+    for (results, stats) in partials {
+        decoded.extend(results.frames);
+        stations.push((results.stations, stats));
+    }
+    decoded.sort_unstable_by_key(|(index, _)| *index);
+
+    let mut stitched = Partial::default();
+    let mut carry: Vec<u8> = Vec::new();
+    let (mut offset, mut lines) = (0, 0);
+    for (_, frame) in decoded {
+        carry.extend_from_slice(&frame.head);
+        if frame.lines > 0 {
+            let start = offset + frame.body_start - carry.len() as u64 - 1;
+            let line = process_chunk(&carry, start, options).map_err(|err| err.shifted(start, lines))?;
+            stitched.accumulate(line);
+            if let Some(err) = frame.error {
+                return Err(err.shifted(offset + frame.body_start, lines + 1).into());
+            }
+            carry = frame.tail;
+        }
+        offset += frame.length;
+        lines += frame.lines;
+    }
+    let start = offset - carry.len() as u64;
+    let last_line = process_chunk(&carry, start, options).map_err(|err| err.shifted(start, lines))?;
+    stitched.accumulate(last_line);
+
+    let (partials, threads): (Vec<Partial>, Vec<_>) = stations.into_iter().unzip();
+    let mut merged = schedule::reduce(partials, Partial::merge).unwrap_or_default();
+    merged.merge(stitched);
+    // the newlines the stitched lines end in were part of no chunk
+    merged.bytes = offset;
+    Ok(merged.into_report(threads))
+}
+
+/// Finds the ranges of the frames in `data`.
+fn frames(data: &[u8]) -> io::Result<Vec<Range<usize>>> {
+    let mut frames = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let size = zstd_safe::find_frame_compressed_size(&data[start..]).map_err(|code| io::Error::new(ErrorKind::InvalidData, zstd_safe::get_error_name(code)))?;
+        frames.push(start..start + size);
+        start += size;
+    }
+    Ok(frames)
+}
+
+/// Decodes one frame and aggregates the complete lines in it.
+fn decode_frame(compressed: &[u8], options: &Options, stations: &mut Partial) -> Result<Frame, Error> {
+    let data = zstd::decode_all(compressed)?;
+    let mut frame = Frame { length: data.len() as u64, lines: count_lines(&data), ..Frame::default() };
+    let (Some(first), Some(last)) = (data.iter().position(|byte| *byte == b'\n'), data.iter().rposition(|byte| *byte == b'\n')) else {
+        frame.head = data;
+        return Ok(frame);
+    };
+    frame.head = data[..first].to_vec();
+    frame.tail = data[last + 1..].to_vec();
+    frame.body_start = first as u64 + 1;
+    // where the frame starts isn't known yet, which is why extremes aren't kept on this path
+    match process_chunk(&data[first + 1..last + 1], 0, options) {
+        Ok(chunk) => stations.accumulate(chunk),
+        // the other frames go on, only the earliest error across all of them is reported
+        Err(err) => frame.error = Some(err),
+    }
+    Ok(frame)
+}
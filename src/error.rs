@@ -0,0 +1,154 @@
+use std::{fmt::Display, io, path::PathBuf};
+
+/// Everything that can go wrong while aggregating a measurements file.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(ParseError),
+    /// Something went wrong with one of several input files.
+    InFile(PathBuf, Box<Error>),
+    /// One of the [`Options`](crate::Options) is out of its range.
+    InvalidOption(&'static str),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Parse(err) => write!(f, "{}", err),
+            Error::InFile(path, err) => write!(f, "{}: {}", path.display(), err),
+            Error::InvalidOption(message) => write!(f, "invalid option: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::InFile(_, err) => Some(err.as_ref()),
+            Error::InvalidOption(_) => None,
+        }
+    }
+}
+
+impl Error {
+    /// Picks the parse error that comes first in the input, when several workers failed.
+    pub(crate) fn earliest(self, other: Error) -> Error {
+        match (self, other) {
+            (Error::Parse(this), Error::Parse(that)) => Error::Parse(if that.offset < this.offset { that } else { this }),
+            (this, _) => this,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// Why a line couldn't be turned into a measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// There is no `;` between the station name and the temperature.
+    MissingDelimiter,
+    /// The temperature isn't a valid number.
+    InvalidNumber,
+    /// The station name is empty.
+    EmptyName,
+    /// The station name isn't valid UTF-8.
+    InvalidUtf8,
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorKind::MissingDelimiter => write!(f, "missing delimiter"),
+            ParseErrorKind::InvalidNumber => write!(f, "invalid temperature value"),
+            ParseErrorKind::EmptyName => write!(f, "empty station name"),
+            ParseErrorKind::InvalidUtf8 => write!(f, "station name is not valid UTF-8"),
+        }
+    }
+}
+
+/// A malformed line, with the position it starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset of the start of the line.
+    pub offset: u64,
+    /// Line number, starting at 1.
+    pub line: u64,
+}
+
+impl ParseError {
+    /// Moves an error found inside a chunk to its position in the whole input.
+    pub(crate) fn shifted(self, offset: u64, lines: u64) -> Self {
+        Self {
+            kind: self.kind,
+            offset: self.offset + offset,
+            line: self.line + lines,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {} (byte offset {}): {}", self.line, self.offset, self.kind)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Number of malformed lines skipped in lenient mode, per kind of error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SkippedLines {
+    pub missing_delimiter: u64,
+    pub invalid_number: u64,
+    pub empty_name: u64,
+    pub invalid_utf8: u64,
+}
+
+impl SkippedLines {
+    pub fn add(&mut self, kind: ParseErrorKind) {
+        match kind {
+            ParseErrorKind::MissingDelimiter => self.missing_delimiter += 1,
+            ParseErrorKind::InvalidNumber => self.invalid_number += 1,
+            ParseErrorKind::EmptyName => self.empty_name += 1,
+            ParseErrorKind::InvalidUtf8 => self.invalid_utf8 += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: SkippedLines) {
+        self.missing_delimiter += other.missing_delimiter;
+        self.invalid_number += other.invalid_number;
+        self.empty_name += other.empty_name;
+        self.invalid_utf8 += other.invalid_utf8;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.missing_delimiter + self.invalid_number + self.empty_name + self.invalid_utf8
+    }
+}
+
+impl Display for SkippedLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "skipped {} malformed lines ({} missing delimiter, {} invalid number, {} empty name, {} invalid UTF-8)",
+            self.total(),
+            self.missing_delimiter,
+            self.invalid_number,
+            self.empty_name,
+            self.invalid_utf8,
+        )
+    }
+}
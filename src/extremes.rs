@@ -0,0 +1,87 @@
+use std::{cmp::Ordering, path::Path, sync::Arc};
+
+/// A reading and where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub value: f64,
+    /// Byte offset of the line the reading is on, from the start of its input.
+    pub offset: u64,
+    /// The input file, when several files are aggregated together.
+    pub file: Option<Arc<Path>>,
+}
+
+impl Reading {
+    /// Orders readings by position, for a deterministic choice between equal values.
+    fn position_cmp(&self, other: &Reading) -> Ordering {
+        (&self.file, self.offset).cmp(&(&other.file, other.offset))
+    }
+}
+
+/// The highest and lowest readings of a station, up to a limit each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extremes {
+    limit: usize,
+    highest: Vec<Reading>,
+    lowest: Vec<Reading>,
+}
+
+impl Extremes {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, highest: Vec::with_capacity(limit + 1), lowest: Vec::with_capacity(limit + 1) }
+    }
+
+    /// The highest readings, highest first, and the earliest first among equal ones.
+    pub fn highest(&self) -> &[Reading] {
+        &self.highest
+    }
+
+    /// The lowest readings, lowest first, and the earliest first among equal ones.
+    pub fn lowest(&self) -> &[Reading] {
+        &self.lowest
+    }
+
+    #[inline]
+    pub fn add(&mut self, value: f64, offset: u64) {
+        // most readings are neither
+        let is_highest = self.highest.len() < self.limit || self.highest.last().is_some_and(|last| value >= last.value);
+        let is_lowest = self.lowest.len() < self.limit || self.lowest.last().is_some_and(|last| value <= last.value);
+        if is_highest {
+            insert(&mut self.highest, self.limit, Reading { value, offset, file: None }, |a, b| b.value.total_cmp(&a.value));
+        }
+        if is_lowest {
+            insert(&mut self.lowest, self.limit, Reading { value, offset, file: None }, |a, b| a.value.total_cmp(&b.value));
+        }
+    }
+
+    pub fn merge(&mut self, other: &Extremes) {
+        for reading in &other.highest {
+            insert(&mut self.highest, self.limit, reading.clone(), |a, b| b.value.total_cmp(&a.value));
+        }
+        for reading in &other.lowest {
+            insert(&mut self.lowest, self.limit, reading.clone(), |a, b| a.value.total_cmp(&b.value));
+        }
+    }
+
+    /// Marks every reading as found in `file`.
+    pub(crate) fn set_file(&mut self, file: &Arc<Path>) {
+        for reading in self.highest.iter_mut().chain(&mut self.lowest) {
+            reading.file = Some(file.clone());
+        }
+    }
+}
+
+/// Inserts `reading` into `readings`, which are sorted by `order` and then by position, keeping at most `limit`.
+fn insert(readings: &mut Vec<Reading>, limit: usize, reading: Reading, order: fn(&Reading, &Reading) -> Ordering) {
+    let index = readings.partition_point(|existing| order(existing, &reading).then_with(|| existing.position_cmp(&reading)) == Ordering::Less);
+    if index < limit {
+        readings.insert(index, reading);
+        readings.truncate(limit);
+    }
+}
+
+/// Any station accumulator that also keeps the extreme readings.
+#[derive(Default)]
+pub(crate) struct ExtremesStation<S> {
+    pub(crate) station: S,
+    pub(crate) extremes: Option<Box<Extremes>>,
+}
@@ -0,0 +1,198 @@
+//! Aggregating several input files in one run.
+//!
+//! The files are laid out end to end, without their header lines, in one range of positions, so
+//! the scheduler hands out chunks of all of them to the same pool of workers, and a chunk may
+//! span the end of one file and the start of the next. The end of a file always ends a line,
+//! even without a trailing newline.
+
+use std::{collections::BTreeMap, fs::File, ops::Range, path::{Path, PathBuf}, sync::Arc};
+
+use memmap2::Mmap;
+
+use crate::{aggregate_open_file, aggregate_report, buffered, build_report, compressed::Compression, header_length, mapped, schedule, Error, FileReport, Options, Partial, PartialResults, Report, Station};
+
+/// How the contents of an input file are read.
+enum Source {
+    Mapped(Mmap),
+    Buffered,
+}
+
+/// A non-empty regular input file and where it lies among all inputs.
+struct Input {
+    /// Position of the file in the list of paths.
+    index: usize,
+    path: PathBuf,
+    source: Source,
+    /// Length of the header lines that are skipped.
+    header: usize,
+    /// Position of the first byte after the header.
+    start: usize,
+    /// Length of the file without the header.
+    length: usize,
+}
+
+impl Input {
+    /// Returns the start of the first line that starts at or after `position`, both counted from
+    /// the end of the header.
+    fn align(&self, position: usize) -> Result<usize, Error> {
+        let position = self.header + position;
+        let aligned = match &self.source {
+            Source::Mapped(data) => mapped::align(data, position),
+            Source::Buffered => buffered::align(&self.path, self.header + self.length, position)?,
+        };
+        Ok(aligned - self.header)
+    }
+
+    /// Aggregates the lines in `range` of the file, counted from the end of the header.
+    fn process(&self, range: Range<usize>, options: &Options, results: &mut Partial) -> Result<(), Error> {
+        let range = self.header + range.start..self.header + range.end;
+        match &self.source {
+            Source::Mapped(data) => {
+                results.accumulate(mapped::process_range(data, range, options)?);
+                Ok(())
+            }
+            Source::Buffered => buffered::process_range(&self.path, range, options, results),
+        }
+    }
+}
+
+/// The results of a worker, per input file with [`Options::per_file`] or [`Options::extremes`], or
+/// all in the first slot otherwise.
+#[derive(Default)]
+struct FileResults {
+    files: Vec<Partial>,
+}
+
+impl FileResults {
+    fn file(&mut self, index: usize) -> &mut Partial {
+        if self.files.len() <= index {
+            self.files.resize_with(index + 1, Partial::default);
+        }
+        &mut self.files[index]
+    }
+
+    fn merge(&mut self, other: FileResults) {
+        for (index, partial) in other.files.into_iter().enumerate() {
+            self.file(index).merge(partial);
+        }
+    }
+}
+
+/// Reads all measurements files in `paths` and returns the combined statistics of every station,
+/// sorted by name.
+///
+/// Chunks of all regular files are distributed over one pool of worker threads; pipes, other
+/// special files and compressed files are aggregated one after the other. Errors name the file
+/// they occurred in.
+pub fn aggregate_files<P: AsRef<Path>>(paths: &[P], options: &Options) -> Result<BTreeMap<String, Station>, Error> {
+    Ok(aggregate_files_report(paths, options)?.stations)
+}
+
+/// Like [`aggregate_files`], but also reports the malformed lines skipped in lenient mode and,
+/// with [`Options::per_file`], the statistics of every file on its own.
+pub fn aggregate_files_report<P: AsRef<Path>>(paths: &[P], options: &Options) -> Result<Report, Error> {
+    options.validate()?;
+    if let [path] = paths {
+        let path = path.as_ref();
+        let mut report = aggregate_report(path, options).map_err(|err| in_file(path, err))?;
+        if options.per_file {
+            report.files = vec![FileReport { path: path.to_owned(), stations: report.stations.clone() }];
+        }
+        return Ok(report);
+    }
+
+    let mut inputs = Vec::new();
+    let mut streams = Vec::new();
+    let mut length = 0;
+    for (index, path) in paths.iter().enumerate() {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(|err| in_file(path, err.into()))?;
+        let metadata = file.metadata().map_err(|err| in_file(path, err.into()))?;
+        if !metadata.is_file() || Compression::sniff(&mut file).map_err(|err| in_file(path, err.into()))?.is_some() {
+            streams.push((index, path, file));
+            continue;
+        }
+        let file_length: usize = metadata.len().try_into().expect("Couldn't convert len from u64 to usize");
+        if file_length == 0 {
+            continue;
+        }
+        let source = match options.mmap && options.window_size.is_none() {
+            // SAFETY: the mapping is only read, and the file is expected not to be modified while we run.
+            // If it is truncated concurrently, reads from the mapping may fault.
+            true => unsafe { Mmap::map(&file) }.map_or(Source::Buffered, Source::Mapped),
+            false => Source::Buffered,
+        };
+        let header = match &source {
+            Source::Mapped(data) => header_length(data, options.skip_header),
+            Source::Buffered => buffered::header_length(path, options.skip_header).map_err(|err| in_file(path, err))?,
+        };
+        if header >= file_length {
+            continue;
+        }
+        let file_length = file_length - header;
+        inputs.push(Input { index, path: path.to_owned(), source, header, start: length, length: file_length });
+        length += file_length;
+    }
+
+    let input_at = |position: usize| &inputs[inputs.partition_point(|input| input.start <= position) - 1];
+    // the positions of extreme readings are only meaningful together with their file
+    let separate = options.per_file || options.extremes > 0;
+    let slot = |input: &Input| if separate { input.index } else { 0 };
+    let align = |position: usize| {
+        if position >= length {
+            return Ok(length);
+        }
+        let input = input_at(position);
+        let aligned = input.align(position - input.start).map_err(|err| in_file(&input.path, err))?;
+        Ok(input.start + aligned)
+    };
+    let partials = schedule::run(0..length, options, align, |thread_results: &mut FileResults, range| {
+        let mut position = range.start;
+        while position < range.end {
+            let input = input_at(position);
+            let end = range.end.min(input.start + input.length);
+            let results = thread_results.file(slot(input));
+            input.process(position - input.start..end - input.start, options, results).map_err(|err| in_file(&input.path, err))?;
+            position = end;
+        }
+        Ok(())
+    })?;
+    let (partials, threads): (Vec<_>, Vec<_>) = partials.into_iter().unzip();
+    let mut results = schedule::reduce(partials, FileResults::merge).unwrap_or_default();
+
+    for (index, path, file) in streams {
+        let report = aggregate_open_file(path, file, options).map_err(|err| in_file(path, err))?;
+        let stations = report.stations.into_iter().map(|(name, station)| (name.into_bytes(), station)).collect();
+        let slot = if separate { index } else { 0 };
+        results.file(slot).merge(Partial { stations, skipped: report.skipped, bytes: report.bytes });
+    }
+
+    let mut total = Partial::default();
+    let mut files = Vec::new();
+    let mut partials = results.files.into_iter();
+    if separate {
+        for path in paths {
+            let mut partial = partials.next().unwrap_or_default();
+            if options.extremes > 0 {
+                let file: Arc<Path> = path.as_ref().into();
+                partial.stations.values_mut().for_each(|station| station.set_file(&file));
+            }
+            if options.per_file {
+                let stations = partial.stations.iter().map(|(name, station)| (name, station.clone()));
+                let report = build_report(stations, Default::default(), Vec::new());
+                files.push(FileReport { path: path.as_ref().to_owned(), stations: report.stations });
+            }
+            total.merge(partial);
+        }
+    }
+    for partial in partials {
+        total.merge(partial);
+    }
+    let mut report = total.into_report(threads);
+    report.files = files;
+    Ok(report)
+}
+
+fn in_file(path: &Path, err: Error) -> Error {
+    Error::InFile(path.to_owned(), Box::new(err))
+}
@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+
+use crate::FixedStation;
+
+/// Number of temperatures in the 1BRC format, from -99.9 to 99.9.
+const BINS: usize = 1999;
+
+/// How often every temperature in the 1BRC format was read, for exact percentiles.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bins: [u32; BINS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { bins: [0; BINS] }
+    }
+}
+
+impl Histogram {
+    /// Counts a reading in tenths of a degree.
+    #[inline]
+    pub fn add(&mut self, tenths: i16) {
+        self.bins[(tenths + 999) as usize] += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bin, other) in self.bins.iter_mut().zip(&other.bins) {
+            *bin += other;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.bins.iter().map(|count| *count as u64).sum()
+    }
+
+    /// The reading that `percentile` percent of all readings are less than or equal to, by the
+    /// nearest-rank method, so the 50th percentile of an even number of readings is the lower median.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100.0 * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (index, bin) in self.bins.iter().enumerate() {
+            seen += *bin as u64;
+            if seen >= rank {
+                return Some(tenths_of(index));
+            }
+        }
+        unreachable!("the rank is at most the number of readings")
+    }
+
+    /// The most frequent reading, the lowest one if several are equally frequent.
+    pub fn mode(&self) -> Option<f64> {
+        let (index, count) = self.bins.iter().enumerate().fold((0, 0), |max, (index, count)| if *count > max.1 { (index, *count) } else { max });
+        (count > 0).then(|| tenths_of(index))
+    }
+}
+
+fn tenths_of(index: usize) -> f64 {
+    (index as i16 - 999) as f64 / 10.0
+}
+
+impl Debug for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Histogram").field("count", &self.count()).finish()
+    }
+}
+
+/// A [`FixedStation`] that also counts its readings in a histogram.
+#[derive(Default)]
+pub(crate) struct HistogramStation {
+    pub(crate) station: FixedStation,
+    pub(crate) histogram: Option<Box<Histogram>>,
+}
+
+impl HistogramStation {
+    #[inline]
+    pub(crate) fn update(&mut self, tenths: i16) {
+        self.station.update(tenths);
+        self.histogram.get_or_insert_with(Box::default).add(tenths);
+    }
+}
@@ -0,0 +1,74 @@
+//! Estimating the number of distinct station names in constant memory.
+//!
+//! A HyperLogLog hashes every name and keeps, per register, the longest run of leading zero bits
+//! seen among the hashes that fall into it. Registers are combined with a harmonic mean, and
+//! small counts, where many registers are still empty, are estimated by linear counting instead,
+//! as in Flajolet et al., "HyperLogLog: the analysis of a near-optimal cardinality estimation
+//! algorithm". With 64-bit hashes, no correction for large counts is needed.
+
+use crate::table::hash_name;
+
+/// A mergeable estimate of the number of distinct names added to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Creates an empty estimate with `2^precision` registers of one byte each. The standard
+    /// error is about `1.04 / sqrt(2^precision)`, so 14 gives about 0.8% with 16 KiB.
+    pub fn new(precision: u8) -> Self {
+        assert!((4..=18).contains(&precision), "the precision of a HyperLogLog must be from 4 to 18");
+        Self { precision, registers: vec![0; 1 << precision] }
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    pub fn add(&mut self, name: &[u8]) {
+        let hash = mix(hash_name(name));
+        let index = (hash >> (64 - self.precision)) as usize;
+        // the marker bit bounds the run of zeros when the remaining bits are all zero
+        let rest = hash << self.precision | 1 << (self.precision - 1);
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Adds the names of `other`, which must have the same precision.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        assert_eq!(self.precision, other.precision, "only HyperLogLogs of the same precision can be merged");
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimates the number of distinct names added.
+    pub fn estimate(&self) -> f64 {
+        let registers = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / registers),
+        };
+        let sum: f64 = self.registers.iter().map(|rank| 2f64.powi(-(*rank as i32))).sum();
+        let estimate = alpha * registers * registers / sum;
+        let empty = self.registers.iter().filter(|rank| **rank == 0).count();
+        if estimate <= 2.5 * registers && empty > 0 {
+            return registers * (registers / empty as f64).ln();
+        }
+        estimate
+    }
+}
+
+/// Spreads the bits of a name hash, which is tuned for table lookups rather than for uniform
+/// high bits, with the finalizer of MurmurHash3.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ hash >> 33
+}
@@ -0,0 +1,502 @@
+use core::str;
+use std::{collections::{BTreeMap, HashMap}, fs::File, path::{Path, PathBuf}, thread};
+
+use memmap2::Mmap;
+
+mod buffered;
+mod compressed;
+mod error;
+mod extremes;
+mod files;
+mod histogram;
+mod hyperloglog;
+mod lines;
+mod mapped;
+mod output;
+mod parse;
+mod scan;
+mod schedule;
+mod station;
+mod stream;
+mod summary;
+mod table;
+mod tdigest;
+
+use buffered::aggregate_buffered;
+use compressed::Compression;
+pub use error::{Error, ParseError, ParseErrorKind, SkippedLines};
+pub use extremes::{Extremes, Reading};
+pub use files::{aggregate_files, aggregate_files_report};
+pub use histogram::Histogram;
+pub use hyperloglog::HyperLogLog;
+pub use mapped::{aggregate_slice, aggregate_slice_report};
+pub use output::{write_per_file, write_results, write_summary, Format, OutputOptions, Rounding};
+pub use parse::{parse_tenths, InputFormat, LineEnding};
+pub use schedule::{Chunking, ThreadStats};
+pub use station::{FixedStation, Station};
+pub use stream::{aggregate_reader, aggregate_reader_report, aggregate_reader_summary};
+pub use summary::Summary;
+pub use tdigest::TDigest;
+#[cfg(target_arch = "x86_64")]
+use scan::{Avx2Scan, Sse2Scan};
+use extremes::ExtremesStation;
+use histogram::HistogramStation;
+use lines::{Lines, Measurement};
+use parse::parse_tenths_prefix;
+use scan::{FindDelimiter, ScalarScan, Scanner, SwarScan};
+use table::{hash_name, StationTable};
+use tdigest::SketchStation;
+
+/// Settings for a single aggregation run.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// How a file is divided between the worker threads.
+    pub chunking: Chunking,
+    /// Size of the chunks that workers claim with [`Chunking::Queue`].
+    pub chunk_size: usize,
+    /// Size of the blocks a stream is cut into before they are handed to the workers.
+    pub block_size: usize,
+    /// Memory-map regular files instead of reading every chunk into its own buffer.
+    pub mmap: bool,
+    /// Read every chunk in windows of this many bytes, bounding memory use to about
+    /// `threads * window_size` regardless of the file size. Implies buffered reading.
+    pub window_size: Option<usize>,
+    /// Assume the 1BRC temperature format and parse into integer tenths instead of `f64`, which is
+    /// faster and exact, but rejects readings without exactly one decimal.
+    pub fixed_point: bool,
+    /// What to do with malformed lines.
+    pub errors: ErrorMode,
+    /// Scan for delimiters with the fastest vector instructions the CPU supports, instead of byte by byte.
+    pub simd: bool,
+    /// Delimiter, decimal separator and line ending of the input.
+    pub input_format: InputFormat,
+    /// Number of header lines to skip at the start of every input.
+    pub skip_header: usize,
+    /// Skip lines that start with this, like the `#` lines at the top of `weather_stations.csv`.
+    pub comment_prefix: Option<String>,
+    /// Count every reading in a [`Histogram`] for exact percentiles. Needs `fixed_point`.
+    pub histograms: bool,
+    /// Summarize the readings of every station in a [`TDigest`] of this compression, for
+    /// approximate percentiles of readings with any number of decimals. Readings are parsed as
+    /// `f64`, so this takes precedence over `fixed_point` and `histograms`.
+    pub sketch: Option<f64>,
+    /// Keep this many of the highest and of the lowest readings of every station, with their
+    /// positions in the input. Zero keeps none.
+    pub extremes: usize,
+    /// Also keep the results of every input file in [`Report::files`] when aggregating several files.
+    pub per_file: bool,
+    /// Precision of the [`HyperLogLog`] that [`aggregate_reader_summary`] estimates the number of
+    /// distinct stations with, from 4 to 18. Higher is more accurate and uses more memory.
+    pub distinct_precision: u8,
+}
+
+/// How malformed lines are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorMode {
+    /// Stop at the first malformed line.
+    #[default]
+    Strict,
+    /// Skip malformed lines and count them in [`Report::skipped`].
+    Lenient,
+}
+
+/// Everything an aggregation run found out about its input.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Statistics of every station, sorted by name.
+    pub stations: BTreeMap<String, Station>,
+    /// Malformed lines that were skipped in lenient mode.
+    pub skipped: SkippedLines,
+    /// Size of the measurements read, after decompression and without the skipped header lines.
+    pub bytes: u64,
+    /// How busy each worker thread was.
+    pub threads: Vec<ThreadStats>,
+    /// Statistics of every input file on its own, in the order the files were given, if
+    /// [`Options::per_file`] is set.
+    pub files: Vec<FileReport>,
+}
+
+/// The stations of one input file in a run over several files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub stations: BTreeMap<String, Station>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            chunking: Chunking::default(),
+            chunk_size: 16 * 1024 * 1024,
+            block_size: 8 * 1024 * 1024,
+            mmap: true,
+            window_size: None,
+            fixed_point: false,
+            errors: ErrorMode::default(),
+            simd: true,
+            input_format: InputFormat::default(),
+            skip_header: 0,
+            comment_prefix: None,
+            histograms: false,
+            sketch: None,
+            extremes: 0,
+            per_file: false,
+            distinct_precision: 14,
+        }
+    }
+}
+
+impl Options {
+    /// Rejects options the workers would panic on, before any of them is started.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.sketch.is_some_and(|compression| !(compression.is_finite() && compression >= 1.0)) {
+            return Err(Error::InvalidOption("the sketch compression must be a finite number of at least 1"));
+        }
+        Ok(())
+    }
+}
+
+/// Reads the measurements file at `path` and returns the statistics of every station, sorted by name.
+///
+/// Regular files are memory-mapped unless `options.mmap` is off, a `window_size` is set or mapping
+/// fails, in which case each worker reads its chunk into a buffer. Pipes and other special files are
+/// streamed, and so are gzip and zstd compressed files, except that the frames of a memory-mapped
+/// zstd file are decoded in parallel.
+pub fn aggregate<P: AsRef<Path>>(path: P, options: &Options) -> Result<BTreeMap<String, Station>, Error> {
+    Ok(aggregate_report(path, options)?.stations)
+}
+
+/// Like [`aggregate`], but also reports the malformed lines skipped in lenient mode.
+pub fn aggregate_report<P: AsRef<Path>>(path: P, options: &Options) -> Result<Report, Error> {
+    options.validate()?;
+    let path = path.as_ref();
+    aggregate_open_file(path, File::open(path)?, options)
+}
+
+/// Aggregates the file at `path` that is already open as `file`.
+fn aggregate_open_file(path: &Path, mut file: File, options: &Options) -> Result<Report, Error> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return aggregate_reader_report(file, options);
+    }
+    match Compression::sniff(&mut file)? {
+        Some(Compression::Zstd) if options.mmap => {
+            // SAFETY: the mapping is only read, and the file is expected not to be modified while we run.
+            if let Ok(mmap) = unsafe { Mmap::map(&file) } {
+                return compressed::aggregate_zstd(&mmap, options);
+            }
+            return aggregate_reader_report(file, options);
+        }
+        Some(_) => return aggregate_reader_report(file, options),
+        None => {}
+    }
+
+    if options.mmap && options.window_size.is_none() {
+        // SAFETY: the mapping is only read, and the file is expected not to be modified while we run.
+        // If it is truncated concurrently, reads from the mapping may fault.
+        if let Ok(mmap) = unsafe { Mmap::map(&file) } {
+            return aggregate_slice_report(&mmap, options);
+        }
+    }
+
+    let length: usize = metadata.len().try_into().expect("Couldn't convert len from u64 to usize");
+    aggregate_buffered(path, length, options)
+}
+
+/// What a worker thread hands back: its stations and skipped lines, merged pairwise with the
+/// results of the other workers before the report is built.
+trait PartialResults: Default + Send {
+    fn merge(&mut self, other: Self);
+
+    /// Sorts the stations by name, once, for the final report.
+    fn into_report(self, threads: Vec<ThreadStats>) -> Report;
+}
+
+/// Merges the results of all workers in a parallel tree reduction and builds the report.
+fn finish<P: PartialResults>(partials: Vec<(P, ThreadStats)>) -> Report {
+    let (partials, threads): (Vec<P>, Vec<ThreadStats>) = partials.into_iter().unzip();
+    schedule::reduce(partials, P::merge).unwrap_or_default().into_report(threads)
+}
+
+fn build_report<N, I>(stations: I, skipped: SkippedLines, threads: Vec<ThreadStats>) -> Report
+where
+    N: AsRef<[u8]>,
+    I: IntoIterator<Item = (N, Station)>,
+{
+    let stations = stations
+        .into_iter()
+        .map(|(name, station)| (str::from_utf8(name.as_ref()).expect("Station names are validated while parsing").to_string(), station))
+        .collect();
+    Report { stations, skipped, bytes: 0, threads, files: Vec::new() }
+}
+
+/// The results of a worker that processes several chunks, owning the station names.
+#[derive(Default)]
+struct Partial {
+    stations: HashMap<Vec<u8>, Station>,
+    skipped: SkippedLines,
+    bytes: u64,
+}
+
+impl Partial {
+    /// Merges the results of one chunk, copying only names seen for the first time.
+    fn accumulate(&mut self, chunk: ChunkResults) {
+        for (name, station) in chunk.stations.into_entries() {
+            match self.stations.get_mut(name) {
+                Some(existing) => existing.merge(station),
+                None => {
+                    self.stations.insert(name.to_vec(), station);
+                }
+            }
+        }
+        self.skipped.merge(chunk.skipped);
+        self.bytes += chunk.bytes;
+    }
+}
+
+impl PartialResults for Partial {
+    fn merge(&mut self, other: Partial) {
+        for (name, station) in other.stations {
+            self.stations.entry(name).or_default().merge(station);
+        }
+        self.skipped.merge(other.skipped);
+        self.bytes += other.bytes;
+    }
+
+    fn into_report(self, threads: Vec<ThreadStats>) -> Report {
+        Report { bytes: self.bytes, ..build_report(self.stations, self.skipped, threads) }
+    }
+}
+
+/// The results of one or more chunks, borrowing station names from them.
+struct ChunkResults<'a, S = Station> {
+    stations: StationTable<'a, S>,
+    skipped: SkippedLines,
+    /// Number of lines in the chunks.
+    lines: u64,
+    /// Length of the chunks.
+    bytes: u64,
+}
+
+impl<S: Default> Default for ChunkResults<'_, S> {
+    fn default() -> Self {
+        Self {
+            stations: StationTable::default(),
+            skipped: SkippedLines::default(),
+            lines: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl<'a> PartialResults for ChunkResults<'a> {
+    /// Merges the results of another chunk of the same input.
+    fn merge(&mut self, chunk: ChunkResults<'a>) {
+        for (name, station) in chunk.stations.into_entries() {
+            self.stations.get_or_default(name, hash_name(name)).merge(station);
+        }
+        self.skipped.merge(chunk.skipped);
+        self.lines += chunk.lines;
+        self.bytes += chunk.bytes;
+    }
+
+    fn into_report(self, threads: Vec<ThreadStats>) -> Report {
+        Report { bytes: self.bytes, ..build_report(self.stations.into_entries(), self.skipped, threads) }
+    }
+}
+
+/// A running statistic that readings can be parsed for and added to.
+trait Accumulator: Default {
+    type Value: Copy;
+
+    /// Parses the reading at the start of `rest`, returning it with the number of bytes it took.
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(Self::Value, usize)>;
+
+    /// Converts a parsed reading to degrees.
+    fn reading(value: Self::Value) -> f64;
+
+    /// Adds a reading from the line at byte `offset` of the input.
+    fn add(&mut self, value: Self::Value, offset: u64, options: &Options);
+}
+
+impl Accumulator for Station {
+    type Value = f64;
+
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(f64, usize)> {
+        // arbitrary numbers can only be told apart by the end of the line
+        let mut length = rest.iter().position(|byte| *byte == b'\n').unwrap_or(rest.len());
+        if format.line_ending == LineEnding::CrLf && rest[..length].ends_with(b"\r") {
+            length -= 1;
+        }
+        let text = str::from_utf8(&rest[..length]).ok()?;
+        let value: f64 = match format.decimal_separator {
+            b'.' => text.parse().ok()?,
+            separator => text.replacen(separator as char, ".", 1).parse().ok()?,
+        };
+        // `NaN` and `inf` parse, but aren't temperatures
+        value.is_finite().then_some((value, length))
+    }
+
+    fn reading(value: f64) -> f64 {
+        value
+    }
+
+    fn add(&mut self, value: f64, _offset: u64, _options: &Options) {
+        self.update(value);
+    }
+}
+
+impl Accumulator for FixedStation {
+    type Value = i16;
+
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(i16, usize)> {
+        parse_tenths_prefix(rest, format.decimal_separator)
+    }
+
+    fn reading(value: i16) -> f64 {
+        value as f64 / 10.0
+    }
+
+    fn add(&mut self, value: i16, _offset: u64, _options: &Options) {
+        self.update(value);
+    }
+}
+
+impl Accumulator for SketchStation {
+    type Value = f64;
+
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(f64, usize)> {
+        Station::parse(rest, format)
+    }
+
+    fn reading(value: f64) -> f64 {
+        value
+    }
+
+    fn add(&mut self, value: f64, _offset: u64, options: &Options) {
+        self.update(value, options.sketch.expect("sketch stations are only used with a compression"));
+    }
+}
+
+impl Accumulator for HistogramStation {
+    type Value = i16;
+
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(i16, usize)> {
+        FixedStation::parse(rest, format)
+    }
+
+    fn reading(value: i16) -> f64 {
+        value as f64 / 10.0
+    }
+
+    fn add(&mut self, value: i16, _offset: u64, _options: &Options) {
+        self.update(value);
+    }
+}
+
+impl<S: Accumulator> Accumulator for ExtremesStation<S> {
+    type Value = S::Value;
+
+    fn parse(rest: &[u8], format: &InputFormat) -> Option<(S::Value, usize)> {
+        S::parse(rest, format)
+    }
+
+    fn reading(value: S::Value) -> f64 {
+        S::reading(value)
+    }
+
+    fn add(&mut self, value: S::Value, offset: u64, options: &Options) {
+        self.extremes.get_or_insert_with(|| Box::new(Extremes::new(options.extremes))).add(S::reading(value), offset);
+        self.station.add(value, offset, options);
+    }
+}
+
+/// Aggregates all lines of a newline-aligned chunk that starts at byte `offset` of the input.
+///
+/// Errors are positioned relative to the start of the chunk.
+fn process_chunk<'a>(contents: &'a [u8], offset: u64, options: &Options) -> Result<ChunkResults<'a>, ParseError> {
+    let scanner = match options.simd {
+        true => Scanner::detect(),
+        false => Scanner::Scalar,
+    };
+    match scanner {
+        Scanner::Scalar => process_chunk_with::<ScalarScan>(contents, offset, options),
+        Scanner::Swar => process_chunk_with::<SwarScan>(contents, offset, options),
+        #[cfg(target_arch = "x86_64")]
+        Scanner::Sse2 => process_chunk_with::<Sse2Scan>(contents, offset, options),
+        #[cfg(target_arch = "x86_64")]
+        Scanner::Avx2 => process_chunk_with::<Avx2Scan>(contents, offset, options),
+    }
+}
+
+/// Picks the accumulator that keeps what `options` ask for.
+fn process_chunk_with<'a, F: FindDelimiter>(contents: &'a [u8], offset: u64, options: &Options) -> Result<ChunkResults<'a>, ParseError> {
+    match (options.sketch.is_some(), options.fixed_point, options.histograms, options.extremes > 0) {
+        (true, _, _, false) => scan_stations::<SketchStation, F>(contents, offset, options),
+        (true, _, _, true) => scan_stations::<ExtremesStation<SketchStation>, F>(contents, offset, options),
+        (false, false, _, false) => scan_chunk::<Station, F>(contents, offset, options),
+        (false, false, _, true) => scan_stations::<ExtremesStation<Station>, F>(contents, offset, options),
+        (false, true, false, false) => scan_stations::<FixedStation, F>(contents, offset, options),
+        (false, true, false, true) => scan_stations::<ExtremesStation<FixedStation>, F>(contents, offset, options),
+        (false, true, true, false) => scan_stations::<HistogramStation, F>(contents, offset, options),
+        (false, true, true, true) => scan_stations::<ExtremesStation<HistogramStation>, F>(contents, offset, options),
+    }
+}
+
+/// Scans a chunk with another accumulator and converts its results into [`Station`]s.
+fn scan_stations<'a, S: Accumulator + Into<Station>, F: FindDelimiter>(contents: &'a [u8], offset: u64, options: &Options) -> Result<ChunkResults<'a>, ParseError> {
+    let chunk = scan_chunk::<S, F>(contents, offset, options)?;
+    let mut stations = StationTable::with_capacity(chunk.stations.len());
+    for (name, station) in chunk.stations.into_entries() {
+        *stations.get_or_default(name, hash_name(name)) = station.into();
+    }
+    Ok(ChunkResults { stations, skipped: chunk.skipped, lines: chunk.lines, bytes: chunk.bytes })
+}
+
+/// Splits a chunk into lines and feeds each temperature into the station it belongs to.
+fn scan_chunk<'a, S: Accumulator, F: FindDelimiter>(contents: &'a [u8], offset: u64, options: &Options) -> Result<ChunkResults<'a, S>, ParseError> {
+    let mut results = ChunkResults::<S>::default();
+    let mut lines = Lines::<S, F>::new(contents, options.input_format, options.comment_prefix.as_deref().map(str::as_bytes));
+    for line in lines.by_ref() {
+        if let Err(err) = line.and_then(|measurement| add_measurement(measurement, offset, &mut results.stations, options)) {
+            match options.errors {
+                ErrorMode::Strict => return Err(err),
+                ErrorMode::Lenient => results.skipped.add(err.kind),
+            }
+        }
+    }
+    results.lines = lines.lines_read();
+    results.bytes = contents.len() as u64;
+    Ok(results)
+}
+
+/// Adds one reading to its station, in a chunk that starts at byte `chunk_offset` of the input.
+fn add_measurement<'a, S: Accumulator>(measurement: Measurement<'a, S::Value>, chunk_offset: u64, stations: &mut StationTable<'a, S>, options: &Options) -> Result<(), ParseError> {
+    let Measurement { name, hash, value, offset, line } = measurement;
+    match stations.get_mut(name, hash) {
+        Some(station) => station.add(value, chunk_offset + offset as u64, options),
+        None => {
+            // names are only validated the first time they show up in a chunk
+            str::from_utf8(name).map_err(|_| ParseError { kind: ParseErrorKind::InvalidUtf8, offset: offset as u64, line })?;
+            stations.get_or_default(name, hash).add(value, chunk_offset + offset as u64, options);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the length of the first `lines` lines of `data`, or of all of it if it has fewer.
+fn header_length(data: &[u8], lines: usize) -> usize {
+    if lines == 0 {
+        return 0;
+    }
+    let mut newlines = data.iter().enumerate().filter(|(_, byte)| **byte == b'\n');
+    newlines.nth(lines - 1).map_or(data.len(), |(newline, _)| newline + 1)
+}
+
+/// Counts the lines in `data`, for positioning errors.
+fn count_lines(data: &[u8]) -> u64 {
+    data.iter().filter(|byte| **byte == b'\n').count() as u64
+}
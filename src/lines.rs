@@ -0,0 +1,297 @@
+//! Splitting a chunk into measurements in a single pass.
+//!
+//! Every line is read once: the scanner finds the delimiter, the temperature is parsed forward
+//! from there, and the line must end right after it. Only the name is read again, a word at a
+//! time, to hash it for the station table once it is known where the name ends. Names may
+//! contain the delimiter, so when the text after a delimiter isn't a temperature running up to
+//! the end of the line, that delimiter is part of the name and the next one is tried. This also
+//! tells `station,12,3` apart with a comma as both the delimiter and the decimal separator.
+//!
+//! Comment lines belong to the chunk they start in like any other line, so they are recognized
+//! even when they cross a chunk boundary. The last line of a chunk doesn't need a trailing
+//! newline and goes through the same path as all others.
+
+use std::marker::PhantomData;
+
+use crate::{scan::FindDelimiter, table::hash_name, Accumulator, InputFormat, LineEnding, ParseError, ParseErrorKind};
+
+/// A well-formed line.
+pub(crate) struct Measurement<'a, V> {
+    pub name: &'a [u8],
+    /// Hash of `name` for the station table.
+    pub hash: u64,
+    pub value: V,
+    /// Where the line starts, relative to the start of the chunk.
+    pub offset: usize,
+    /// One-based number of the line within the chunk.
+    pub line: u64,
+}
+
+/// Iterates over the measurements of a newline-aligned chunk, parsing values for `S` and finding
+/// delimiters with `F`.
+///
+/// Blank lines are skipped; malformed lines are yielded as errors positioned relative to the
+/// chunk, and iteration continues with the next line.
+pub(crate) struct Lines<'a, 'f, S, F> {
+    data: &'a [u8],
+    format: InputFormat,
+    /// Lines starting with this are skipped like blank lines.
+    comment_prefix: Option<&'f [u8]>,
+    position: usize,
+    lines: u64,
+    marker: PhantomData<(S, F)>,
+}
+
+impl<'a, 'f, S: Accumulator, F: FindDelimiter> Lines<'a, 'f, S, F> {
+    pub(crate) fn new(data: &'a [u8], format: InputFormat, comment_prefix: Option<&'f [u8]>) -> Self {
+        Self { data, format, comment_prefix, position: 0, lines: 0, marker: PhantomData }
+    }
+
+    /// Number of lines read so far, including blank and malformed ones.
+    pub(crate) fn lines_read(&self) -> u64 {
+        self.lines
+    }
+
+    /// If the content of a line ends at `index`, returns where the next line starts.
+    #[inline]
+    fn next_line(&self, index: usize) -> Option<usize> {
+        match self.data.get(index) {
+            None | Some(b'\n') => Some(index + 1),
+            Some(b'\r') if self.format.line_ending == LineEnding::CrLf => match self.data.get(index + 1) {
+                None | Some(b'\n') => Some(index + 2),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Reads the line starting at `start` and moves past it. Blank and comment lines give `None`.
+    #[inline]
+    fn read_line(&mut self, start: usize) -> Result<Option<Measurement<'a, S::Value>>, ParseErrorKind> {
+        let data = self.data;
+        if let Some(prefix) = self.comment_prefix {
+            if data[start..].starts_with(prefix) {
+                self.position = data[start..].iter().position(|byte| *byte == b'\n').map_or(data.len(), |newline| start + newline + 1);
+                return Ok(None);
+            }
+        }
+        let delimiter_byte = self.format.delimiter;
+        let mut delimiter = F::find(data, start, delimiter_byte);
+        if delimiter == data.len() || data[delimiter] == b'\n' {
+            self.position = delimiter + 1;
+            return match self.next_line(start) == Some(self.position) {
+                true => Ok(None),
+                false => Err(ParseErrorKind::MissingDelimiter),
+            };
+        }
+        loop {
+            if let Some((value, length)) = S::parse(&data[delimiter + 1..], &self.format) {
+                if let Some(next) = self.next_line(delimiter + 1 + length) {
+                    self.position = next;
+                    if delimiter == start {
+                        return Err(ParseErrorKind::EmptyName);
+                    }
+                    let name = &data[start..delimiter];
+                    return Ok(Some(Measurement { name, hash: hash_name(name), value, offset: start, line: self.lines }));
+                }
+            }
+            // no temperature up to the end of the line, so this delimiter belongs to the name
+            let next = F::find(data, delimiter + 1, delimiter_byte);
+            if next == data.len() || data[next] == b'\n' {
+                self.position = next + 1;
+                return match delimiter == start {
+                    true => Err(ParseErrorKind::EmptyName),
+                    false => Err(ParseErrorKind::InvalidNumber),
+                };
+            }
+            delimiter = next;
+        }
+    }
+}
+
+impl<'a, S: Accumulator, F: FindDelimiter> Iterator for Lines<'a, '_, S, F> {
+    type Item = Result<Measurement<'a, S::Value>, ParseError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position < self.data.len() {
+            let start = self.position;
+            self.lines += 1;
+            match self.read_line(start) {
+                Ok(Some(measurement)) => return Some(Ok(measurement)),
+                Ok(None) => continue,
+                Err(kind) => return Some(Err(ParseError { kind, offset: start as u64, line: self.lines })),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scan::{ScalarScan, SwarScan}, FixedStation, Station};
+
+    /// A line's name and value, or its error kind, offset and line number.
+    type Read<V> = Result<(String, V), (ParseErrorKind, u64, u64)>;
+
+    /// Reads every line of `data`.
+    fn read<S: Accumulator, F: FindDelimiter>(data: &str) -> Vec<Read<S::Value>> {
+        read_with::<S, F>(data, InputFormat::default())
+    }
+
+    fn read_with<S: Accumulator, F: FindDelimiter>(data: &str, format: InputFormat) -> Vec<Read<S::Value>> {
+        Lines::<S, F>::new(data.as_bytes(), format, None)
+            .map(|line| match line {
+                Ok(measurement) => {
+                    assert_eq!(measurement.hash, hash_name(measurement.name));
+                    assert!(data[measurement.offset..].starts_with(std::str::from_utf8(measurement.name).unwrap()));
+                    Ok((String::from_utf8(measurement.name.to_vec()).unwrap(), measurement.value))
+                }
+                Err(err) => Err((err.kind, err.offset, err.line)),
+            })
+            .collect()
+    }
+
+    /// Runs `check` with every scanner this CPU supports.
+    fn for_each_scanner(check: impl Fn(&dyn Fn(&str) -> Vec<Read<i16>>)) {
+        check(&read::<FixedStation, ScalarScan>);
+        check(&read::<FixedStation, SwarScan>);
+        #[cfg(target_arch = "x86_64")]
+        {
+            check(&read::<FixedStation, crate::scan::Sse2Scan>);
+            if is_x86_feature_detected!("avx2") {
+                check(&read::<FixedStation, crate::scan::Avx2Scan>);
+            }
+        }
+    }
+
+    fn ok(name: &str, value: i16) -> Read<i16> {
+        Ok((name.to_owned(), value))
+    }
+
+    #[test]
+    fn final_line_with_and_without_newline() {
+        for_each_scanner(|read| {
+            assert_eq!(read("Hamburg;12.0\nBulawayo;8.9\n"), [ok("Hamburg", 120), ok("Bulawayo", 89)]);
+            assert_eq!(read("Hamburg;12.0\nBulawayo;8.9"), [ok("Hamburg", 120), ok("Bulawayo", 89)]);
+            assert_eq!(read("Palembang;-38.8"), [ok("Palembang", -388)]);
+        });
+    }
+
+    #[test]
+    fn names_containing_semicolons() {
+        for_each_scanner(|read| {
+            assert_eq!(read("a;b;1.0\n"), [ok("a;b", 10)]);
+            assert_eq!(read(";a;-2.5"), [ok(";a", -25)]);
+            // a temperature followed by more text is part of the name
+            assert_eq!(read("a;1.0;2.0\nx;;;3.3\n"), [ok("a;1.0", 20), ok("x;;", 33)]);
+            // long enough to cross the vector width of every scanner
+            let name = "St. John's;Newfoundland;and;Labrador;Canada;North America";
+            assert_eq!(read(&format!("{};-5.3\n{};12.1", name, name)), [ok(name, -53), ok(name, 121)]);
+        });
+    }
+
+    #[test]
+    fn blank_lines_are_skipped_but_counted() {
+        for_each_scanner(|read| {
+            assert_eq!(read(""), []);
+            assert_eq!(read("\n\n"), []);
+            assert_eq!(read("\na;1.0\n\nb;2.0\n\n"), [ok("a", 10), ok("b", 20)]);
+            assert_eq!(read("\n\nx\n"), [Err((ParseErrorKind::MissingDelimiter, 2, 3))]);
+        });
+    }
+
+    #[test]
+    fn malformed_lines_are_positioned_and_skipped() {
+        for_each_scanner(|read| {
+            assert_eq!(
+                read("a;1.0\nno delimiter\n;2.0\nb;1.00\nc;\nd;1.0;\n;x;y\ne;3.0"),
+                [
+                    ok("a", 10),
+                    Err((ParseErrorKind::MissingDelimiter, 6, 2)),
+                    Err((ParseErrorKind::EmptyName, 19, 3)),
+                    Err((ParseErrorKind::InvalidNumber, 24, 4)),
+                    Err((ParseErrorKind::InvalidNumber, 31, 5)),
+                    Err((ParseErrorKind::InvalidNumber, 34, 6)),
+                    Err((ParseErrorKind::InvalidNumber, 41, 7)),
+                    ok("e", 30),
+                ]
+            );
+            assert_eq!(read(";x"), [Err((ParseErrorKind::EmptyName, 0, 1))]);
+            assert_eq!(read("a;1.0\nb"), [ok("a", 10), Err((ParseErrorKind::MissingDelimiter, 6, 2))]);
+        });
+    }
+
+    #[test]
+    fn lines_read_counts_every_line() {
+        let mut lines = Lines::<FixedStation, SwarScan>::new(b"a;1.0\n\nbad\nb;2.0", InputFormat::default(), None);
+        assert_eq!(lines.by_ref().count(), 3);
+        assert_eq!(lines.lines_read(), 4);
+    }
+
+    #[test]
+    fn float_values_take_the_rest_of_the_line() {
+        let read = read::<Station, SwarScan>;
+        assert_eq!(read("a;1.25\nb;c;-3\nd;1;2e1"), [Ok(("a".to_owned(), 1.25)), Ok(("b;c".to_owned(), -3.0)), Ok(("d;1".to_owned(), 20.0))]);
+        assert_eq!(read("a;x\n"), [Err((ParseErrorKind::InvalidNumber, 0, 1))]);
+        assert_eq!(
+            read("a;NaN\nb;inf\nc;-infinity\nd;1e999\ne;1.0"),
+            [
+                Err((ParseErrorKind::InvalidNumber, 0, 1)),
+                Err((ParseErrorKind::InvalidNumber, 6, 2)),
+                Err((ParseErrorKind::InvalidNumber, 12, 3)),
+                Err((ParseErrorKind::InvalidNumber, 24, 4)),
+                Ok(("e".to_owned(), 1.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let crlf = InputFormat { line_ending: LineEnding::CrLf, ..InputFormat::default() };
+        let read = |data| read_with::<FixedStation, SwarScan>(data, crlf);
+        assert_eq!(read("a;1.0\r\nb;-2.5\r\n\r\nc;3.0\r\nd;4.0\ne;5.0\r"), [ok("a", 10), ok("b", -25), ok("c", 30), ok("d", 40), ok("e", 50)]);
+        assert_eq!(read("x\r\na;1.0\r\r\n"), [Err((ParseErrorKind::MissingDelimiter, 0, 1)), Err((ParseErrorKind::InvalidNumber, 3, 2))]);
+        // without the option, the carriage return is part of the temperature
+        assert_eq!(read_with::<FixedStation, SwarScan>("a;1.0\r\n", InputFormat::default()), [Err((ParseErrorKind::InvalidNumber, 0, 1))]);
+        let floats = read_with::<Station, SwarScan>("a;1.25\r\nb;-3\r\n", crlf);
+        assert_eq!(floats, [Ok(("a".to_owned(), 1.25)), Ok(("b".to_owned(), -3.0))]);
+    }
+
+    #[test]
+    fn comma_as_delimiter_and_decimal_separator() {
+        let european = InputFormat { delimiter: b',', decimal_separator: b',', line_ending: LineEnding::CrLf };
+        let read = |data| read_with::<FixedStation, ScalarScan>(data, european);
+        assert_eq!(read("station,12,3\r\nSt. Paul, MN,-5,0\n"), [ok("station", 123), ok("St. Paul, MN", -50)]);
+        assert_eq!(read("a,12.3\n"), [Err((ParseErrorKind::InvalidNumber, 0, 1))]);
+        let floats = read_with::<Station, SwarScan>("station,12,25\r\n", european);
+        assert_eq!(floats, [Ok(("station".to_owned(), 12.25))]);
+    }
+
+    #[test]
+    fn tab_delimiter() {
+        let tsv = InputFormat { delimiter: b'\t', line_ending: LineEnding::CrLf, ..InputFormat::default() };
+        for read in [read_with::<FixedStation, SwarScan>, read_with::<FixedStation, ScalarScan>] {
+            assert_eq!(read("station\t12.3\r\nsemi;colon\t-0.5", tsv), [ok("station", 123), ok("semi;colon", -5)]);
+        }
+    }
+
+    #[test]
+    fn comment_lines_are_skipped_but_counted() {
+        let read = |data: &str, prefix: &str| {
+            Lines::<Station, SwarScan>::new(data.as_bytes(), InputFormat::default(), Some(prefix.as_bytes()))
+                .map(|line| line.map(|measurement| measurement.line).map_err(|err| (err.kind, err.line)))
+                .collect::<Vec<_>>()
+        };
+        // the start of weather_stations.csv
+        let weather_stations = "# Adapted from https://simplemaps.com/data/world-cities\n\
+            # Licensed under Creative Commons Attribution 4.0 (https://creativecommons.org/licenses/by/4.0/)\n\
+            Tokyo;35.6897\n\
+            Jakarta;-6.1750\n";
+        assert_eq!(read(weather_stations, "#"), [Ok(3), Ok(4)]);
+        assert_eq!(read("# no; delimiter; here\nTokyo;35.6897\n#", "#"), [Ok(2)]);
+        assert_eq!(read("// comment\na;1.0\n/ not a comment\n", "//"), [Ok(2), Err((ParseErrorKind::MissingDelimiter, 3))]);
+        assert_eq!(read("a;1.0 # trailing\n", "#"), [Err((ParseErrorKind::InvalidNumber, 1))]);
+    }
+}
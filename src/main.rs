@@ -1,164 +1,320 @@
-use core::str;
-use std::{collections::{BTreeMap, HashMap}, fmt::{Debug, Display}, fs::{metadata, File}, io::{Read, Seek, SeekFrom}, sync::{Arc, Mutex}, thread};
-struct Station {
-    min: f64,
-    max: f64,
-    sum: f64,
-    values_read: u64,
+use std::{io::{BufWriter, Write}, path::PathBuf};
+
+use onebrc::{aggregate_files_report, aggregate_reader_report, aggregate_reader_summary, write_per_file, write_results, write_summary, Chunking, ErrorMode, Format, LineEnding, Options, OutputOptions, Rounding, Summary};
+
+const USAGE: &str = "Usage: onebrc [OPTIONS] [FILE|PATTERN...|-]
+
+Aggregates min/mean/max temperatures per station over all given files, or
+glob patterns like \"measurements-*.txt\". FILE defaults to
+../1brc/data/weather_stations.csv, \"-\" reads from stdin. Gzip and zstd
+compressed input is decompressed on the fly.
+
+Options:
+  --threads N                   number of worker threads (default: available cores)
+  --chunking per-thread|queue   one range per thread, or many chunks claimed by idle threads (default)
+  --chunk-size SIZE             size of the queued chunks (default: 16M)
+  --thread-stats                print how busy each worker thread was
+  --window-size SIZE            read chunks in windows of SIZE to bound memory use
+  --block-size SIZE             size of the blocks stdin and pipes are cut into (default: 8M)
+  --no-mmap                     read files into buffers instead of memory-mapping them
+  --no-simd                     scan for delimiters byte by byte, for debugging
+  --fixed-point                 parse temperatures into integer tenths, faster and exact, for
+                                input with exactly one decimal like the 1BRC measurements
+  --delimiter C                 field delimiter, a single character or \"tab\" (default: ;)
+  --decimal-comma               temperatures use a comma as the decimal separator
+  --crlf                        lines end in \\r\\n
+  --skip-header N               skip the first N lines of every input
+  --comment-prefix PREFIX       skip lines that start with PREFIX, e.g. \"#\"
+  --strict                      stop at the first malformed line (default)
+  --lenient                     skip malformed lines and report how many were skipped
+  --format text|json|csv|tsv    output format (default: text)
+  --precision N                 decimal places for CSV and TSV output (default: 1)
+  --rounding reference|half-even
+                                rounding of printed values (default: reference)
+  --stddev                      also print the standard deviation of every station
+  --percentiles LIST            also print these percentiles and the mode of every station,
+                                e.g. 50,95,99, counting every reading in a histogram
+  --sketch COMPRESSION          estimate the percentiles with t-digests instead, for readings
+                                with any number of decimals; higher is more accurate and uses
+                                more memory (e.g. 100)
+  --extremes K                  keep the K highest and lowest readings of every station with
+                                their byte offsets, printed with --format json
+  --per-file                    print the results of every file before the combined results
+  --summary                     only print the number of readings, bytes and stations, the
+                                longest and shortest names and the statistics of all readings
+  --estimate-distinct           with --summary, stream a single input and estimate the number
+                                of stations instead of keeping every one of them
+  -h, --help                    print this help
+
+SIZE is a number of bytes with an optional K, M or G suffix.";
+
+struct Args {
+    inputs: Vec<String>,
+    options: Options,
+    output_options: OutputOptions,
+    thread_stats: bool,
+    summary: bool,
+    estimate_distinct: bool,
 }
 
-impl Default for Station {
-    fn default() -> Self {
-        Self {
-            min: f64::MAX,
-            max: f64::MIN,
-            sum: 0.0,
-            values_read: 0
+fn main() {
+    let Args { inputs, options, output_options, thread_stats, summary, estimate_distinct } = parse_args();
+
+    if estimate_distinct {
+        // the stations are never all in memory, so there is no report to print anything else from
+        let input = &inputs[0];
+        let result = match input.as_str() {
+            "-" => aggregate_reader_summary(std::io::stdin().lock(), &options),
+            path => std::fs::File::open(path).map_err(Into::into).and_then(|file| aggregate_reader_summary(file, &options)),
+        };
+        match result {
+            Ok(summary) => print_summary(&summary, &options, &output_options),
+            Err(err) => {
+                eprintln!("{}: {}", input, err);
+                std::process::exit(1);
+            }
         }
+        return;
     }
-}
 
-impl Station {
-    fn update(&mut self, value: f64) {
-        self.min = self.min.min(value);
-        self.max = self.max.max(value);
-        self.sum += value;
-        self.values_read += 1;
+    // "-" reads the measurements from stdin, errors in files name the file themselves
+    let result = match inputs.as_slice() {
+        [stdin] if stdin == "-" => aggregate_reader_report(std::io::stdin().lock(), &options).map_err(|err| format!("-: {}", err)),
+        _ => aggregate_files_report(&expand(&inputs), &options).map_err(|err| err.to_string()),
+    };
+    let report = match result {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if summary {
+        print_summary(&Summary::from(&report), &options, &output_options);
+    } else {
+        // print results
+        let mut out = BufWriter::new(std::io::stdout().lock());
+        let written = match options.per_file {
+            true => write_per_file(&mut out, &report, &output_options),
+            false => write_results(&mut out, &report.stations, &output_options),
+        };
+        if let Err(err) = written.and_then(|_| out.flush()) {
+            eprintln!("Couldn't write results: {}", err);
+            std::process::exit(1);
+        }
+        if options.errors == ErrorMode::Lenient {
+            eprintln!("{}", report.skipped);
+        }
     }
-    
-    fn merge(&mut self, other: Station) {
-        self.min = self.min.min(other.min);
-        self.max = self.max.max(other.max);
-        self.sum += other.sum;
-        self.values_read += other.values_read;
+    if thread_stats {
+        for (i, stats) in report.threads.iter().enumerate() {
+            eprintln!("thread {}: {} chunks, busy {:.3?}, idle {:.3?}", i, stats.chunks, stats.busy, stats.idle);
+        }
     }
 }
 
-impl Debug for Station {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Station").field("min", &self.min).field("max", &self.max).field("sum", &self.sum).field("values_read", &self.values_read).finish()
+/// Prints `summary` and, in lenient mode, the skipped lines.
+fn print_summary(summary: &Summary, options: &Options, output_options: &OutputOptions) {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    if let Err(err) = write_summary(&mut out, summary, output_options).and_then(|_| out.flush()) {
+        eprintln!("Couldn't write results: {}", err);
+        std::process::exit(1);
     }
-}
-
-impl Display for Station {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:.1}/{:.1}/{:.1}", self.min, self.sum / self.values_read as f64, self.max)
+    if options.errors == ErrorMode::Lenient {
+        eprintln!("{}", summary.skipped);
     }
 }
 
-fn main() {
-    let filename = match std::env::args().skip(1).next() {
-        Some(name) => name,
-        None => "../1brc/data/weather_stations.csv".to_owned(),
+fn parse_args() -> Args {
+    let mut args = Args {
+        inputs: Vec::new(),
+        options: Options::default(),
+        output_options: OutputOptions::default(),
+        thread_stats: false,
+        summary: false,
+        estimate_distinct: false,
     };
-    
-    let length: usize = metadata(filename.clone()).expect("Unable to query file details").len().try_into().expect("Couldn't convert len from u64 to usize");
-    let cores: usize = std::thread::available_parallelism().unwrap().get();
-    // How much each thread should read
-    let division: usize = ((length / cores) as f64).ceil() as usize;
-    let mut starting_offsets = vec![0 as usize];
-    
-    let mut file = File::open(filename.clone()).expect("Unable to open file");
-    
-    // find newline ending for each thread
-    for _i in 0..cores {
-        file.seek(SeekFrom::Current(division as i64)).expect("Couldn't seek to position in file");
-        if (file.stream_position().unwrap() as usize) >= length {
-            break;
-        }
-        let mut buf = vec![0];
-        loop {
-            let _ = file.read(&mut buf);
-            if buf[0] == b'\n' || buf[0] == 0 {
-                break;
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |expected: &str| match iter.next() {
+            Some(value) => value,
+            None => exit_with_usage(&format!("{} expects {}", arg, expected)),
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
             }
-        }
-        starting_offsets.push(file.stream_position().unwrap() as usize);
-    }
-    
-    let results = Arc::new(Mutex::new(BTreeMap::<String, Station>::new()));
-    
-    // Use scoped threads to keep things simpler
-    thread::scope(|scope| {
-        for i in 0..starting_offsets.len() {
-            let filename = filename.clone();
-            let starting_offsets = starting_offsets.clone();
-            let results = results.clone();
-            scope.spawn(move || {
-                // read chunk with the size defined by starting_offsets
-                let mut thread_file = File::open(&filename).expect("Unable to open file");
-                
-                let offset: usize = starting_offsets[i];
-                let size = match i < starting_offsets.len() - 1 {
-                    true => starting_offsets[i + 1] - starting_offsets[i],
-                    false => length - starting_offsets[i],
-                };
-                let mut contents: Vec<u8> = vec![0_u8; size];
-                thread_file.seek(SeekFrom::Start(offset as u64)).expect("Couldn't seek to position in file");
-                thread_file.read(&mut contents).expect("Couldn't read file");
-                
-                // process data
-                if *(contents.last().unwrap()) == b'\n' {
-                    contents.pop();
+            "--threads" => {
+                args.options.threads = match value("a number of threads").parse() {
+                    Ok(threads) if threads > 0 => threads,
+                    _ => exit_with_usage("--threads expects a positive number"),
                 }
-                let mut block_results = HashMap::<&[u8], Station>::new();
-                let content_len = contents.len();
-                let mut i: usize = 0;
-                let mut last_idx: usize = 0;
-                while i < content_len {
-                    if contents[i] == b'\n' {
-                        // process line data
-                        let mut semicolon_idx: usize = 0;
-                        for i in last_idx..i {
-                            if contents[i] == b';' {
-                                semicolon_idx = i;
-                            }
-                        }
-                        
-                        let name = &contents[last_idx..semicolon_idx];
-                        let temp = str::from_utf8(&contents[semicolon_idx + 1..i]).unwrap().parse::<f64>().unwrap();
-                        block_results.entry(name).or_default().update(temp);
-                        
-                        last_idx = i + 1;
-                        i +=1;
-                        continue;
-                    }
-                    i += 1;
+            }
+            "--chunking" => {
+                args.options.chunking = match value("\"per-thread\" or \"queue\"").as_str() {
+                    "per-thread" => Chunking::PerThread,
+                    "queue" => Chunking::Queue,
+                    _ => exit_with_usage("--chunking expects \"per-thread\" or \"queue\""),
                 }
-                if last_idx < content_len - 1 {
-                    // process last line data
-                    let mut semicolon_idx: usize = 0;
-                    for i in last_idx..i {
-                        if contents[i] == b';' {
-                            semicolon_idx = i;
-                        }
+            }
+            "--chunk-size" => args.options.chunk_size = parse_size(&arg, &value("a size")),
+            "--window-size" => args.options.window_size = Some(parse_size(&arg, &value("a size"))),
+            "--block-size" => args.options.block_size = parse_size(&arg, &value("a size")),
+            "--no-mmap" => args.options.mmap = false,
+            "--no-simd" => args.options.simd = false,
+            "--fixed-point" => args.options.fixed_point = true,
+            "--thread-stats" => args.thread_stats = true,
+            "--per-file" => args.options.per_file = true,
+            "--summary" => args.summary = true,
+            "--estimate-distinct" => args.estimate_distinct = true,
+            "--stddev" => args.output_options.stddev = true,
+            "--percentiles" => {
+                let percentiles: Result<Vec<f64>, _> = value("a list of percentiles").split(',').map(str::parse).collect();
+                match percentiles {
+                    Ok(percentiles) if percentiles.iter().all(|percentile| (0.0..=100.0).contains(percentile)) => {
+                        args.output_options.percentiles = percentiles;
+                        args.options.histograms = true;
                     }
-                    let name = &contents[last_idx..semicolon_idx];
-                    let temp = str::from_utf8(&contents[semicolon_idx + 1..i]).unwrap().parse::<f64>().unwrap();
-                    
-                    block_results.entry(name).or_default().update(temp);
+                    _ => exit_with_usage("--percentiles expects a list of percentiles from 0 to 100, like 50,95,99"),
+                }
+            }
+            "--extremes" => {
+                args.options.extremes = match value("a number of readings").parse() {
+                    Ok(extremes) if extremes > 0 => extremes,
+                    _ => exit_with_usage("--extremes expects a positive number of readings"),
+                }
+            }
+            "--sketch" => {
+                args.options.sketch = match value("a compression").parse::<f64>() {
+                    Ok(compression) if compression.is_finite() && compression >= 1.0 => Some(compression),
+                    _ => exit_with_usage("--sketch expects a compression of at least 1, like 100"),
+                }
+            }
+            "--delimiter" => {
+                args.options.input_format.delimiter = match value("a delimiter").as_str() {
+                    "tab" | "\\t" => b'\t',
+                    delimiter if delimiter.len() == 1 && delimiter != "\n" && delimiter != "\r" => delimiter.as_bytes()[0],
+                    _ => exit_with_usage("--delimiter expects a single ASCII character or \"tab\""),
                 }
-                
-                let mut results_writer = results.lock().expect("Could not lock");
-                for (name, station) in block_results {
-                    
-                    results_writer.entry(str::from_utf8(name).unwrap().to_string()).or_default().merge(station);
+            }
+            "--decimal-comma" => args.options.input_format.decimal_separator = b',',
+            "--crlf" => args.options.input_format.line_ending = LineEnding::CrLf,
+            "--skip-header" => {
+                args.options.skip_header = match value("a number of lines").parse() {
+                    Ok(lines) => lines,
+                    Err(_) => exit_with_usage("--skip-header expects a number of lines"),
                 }
-                drop(results_writer);
-            });
+            }
+            "--comment-prefix" => {
+                args.options.comment_prefix = match value("a prefix") {
+                    prefix if prefix.is_empty() => exit_with_usage("--comment-prefix expects a non-empty prefix"),
+                    prefix => Some(prefix),
+                }
+            }
+            "--strict" => args.options.errors = ErrorMode::Strict,
+            "--lenient" => args.options.errors = ErrorMode::Lenient,
+            "--format" => {
+                args.output_options.format = match value("a format").as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "csv" => Format::Csv,
+                    "tsv" => Format::Tsv,
+                    _ => exit_with_usage("--format expects \"text\", \"json\", \"csv\" or \"tsv\""),
+                }
+            }
+            "--precision" => {
+                args.output_options.precision = match value("a number of decimal places").parse() {
+                    Ok(precision) => precision,
+                    Err(_) => exit_with_usage("--precision expects a number of decimal places"),
+                }
+            }
+            "--rounding" => {
+                args.output_options.rounding = match value("\"reference\" or \"half-even\"").as_str() {
+                    "reference" => Rounding::Reference,
+                    "half-even" => Rounding::HalfEven,
+                    _ => exit_with_usage("--rounding expects \"reference\" or \"half-even\""),
+                }
+            }
+            _ if arg.starts_with("--") => exit_with_usage(&format!("unknown option {}", arg)),
+            _ => args.inputs.push(arg),
+        }
+    }
+    if args.options.sketch.is_some() && args.output_options.percentiles.is_empty() {
+        exit_with_usage("--sketch needs --percentiles");
+    }
+    if args.options.histograms && !args.options.fixed_point && args.options.sketch.is_none() {
+        exit_with_usage("--percentiles needs --fixed-point or --sketch");
+    }
+    if args.options.extremes > 0 && args.output_options.format != Format::Json {
+        exit_with_usage("--extremes needs --format json");
+    }
+    if args.summary && (args.options.per_file || args.options.extremes > 0) {
+        exit_with_usage("--summary can't be combined with --per-file or --extremes");
+    }
+    if args.estimate_distinct && !args.summary {
+        exit_with_usage("--estimate-distinct needs --summary");
+    }
+    if args.inputs.is_empty() {
+        args.inputs.push("../1brc/data/weather_stations.csv".to_owned());
+    }
+    if args.inputs.len() > 1 && args.inputs.iter().any(|input| input == "-") {
+        exit_with_usage("\"-\" can't be combined with other inputs");
+    }
+    if args.estimate_distinct && (args.inputs.len() > 1 || args.inputs[0].contains(['*', '?', '['])) {
+        exit_with_usage("--estimate-distinct reads a single input");
+    }
+    args
+}
+
+/// Expands glob patterns into the files they match, in alphabetical order. Other inputs are kept as they are.
+fn expand(inputs: &[String]) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        if !input.contains(['*', '?', '[']) {
+            paths.push(PathBuf::from(input));
+            continue;
+        }
+        let matches = match glob::glob(input) {
+            Ok(matches) => matches,
+            Err(err) => exit_with_usage(&format!("invalid pattern {}: {}", input, err)),
+        };
+        let before = paths.len();
+        for entry in matches {
+            match entry {
+                Ok(path) => paths.push(path),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
         }
-    });
-    
-    // print results
-    print!("{{");
-    let result_map = results.lock().unwrap();
-    let mut iter = result_map.iter().take(result_map.len() - 1).peekable();
-    while iter.peek().is_some() {
-        let (name_val, station) = iter.next().unwrap();
-        print!("{}={}, ", *name_val, station);
-    }
-    
-    let (name_val, station) = result_map.last_key_value().unwrap();
-    print!("{}={}", *name_val, station);
-    println!("}}");
+        if paths.len() == before {
+            eprintln!("{}: no files match this pattern", input);
+            std::process::exit(1);
+        }
+    }
+    paths
+}
+
+/// Parses a byte count like `4096`, `64K`, `16M` or `1G`.
+fn parse_size(arg: &str, value: &str) -> usize {
+    let (digits, factor) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1024),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1024 * 1024),
+        Some(b'G' | b'g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    match digits.parse::<usize>().ok().and_then(|size| size.checked_mul(factor)) {
+        Some(size) if size > 0 => size,
+        _ => exit_with_usage(&format!("{} expects a size like 4096, 64K or 16M", arg)),
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
 }
@@ -0,0 +1,40 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{count_lines, finish, header_length, process_chunk, schedule, ChunkResults, Error, Options, PartialResults, Report, Station};
+
+/// Aggregates measurements that are already in memory, e.g. a memory-mapped file.
+///
+/// Every worker borrows its newline-aligned ranges of `data` directly, so no chunk is copied.
+pub fn aggregate_slice(data: &[u8], options: &Options) -> Result<BTreeMap<String, Station>, Error> {
+    Ok(aggregate_slice_report(data, options)?.stations)
+}
+
+/// Like [`aggregate_slice`], but also reports the malformed lines skipped in lenient mode.
+pub fn aggregate_slice_report(data: &[u8], options: &Options) -> Result<Report, Error> {
+    options.validate()?;
+    let align = |position| Ok(align(data, position));
+    let header = header_length(data, options.skip_header);
+    let partials = schedule::run(header..data.len(), options, align, |thread_results: &mut ChunkResults, range| {
+        thread_results.merge(process_range(data, range, options)?);
+        Ok(())
+    })?;
+
+    Ok(finish(partials))
+}
+
+/// Aggregates the lines in `range` of `data`, positioning errors in the whole of `data`.
+pub(crate) fn process_range<'a>(data: &'a [u8], range: Range<usize>, options: &Options) -> Result<ChunkResults<'a>, Error> {
+    let start = range.start;
+    Ok(process_chunk(&data[range], start as u64, options).map_err(|err| err.shifted(start as u64, count_lines(&data[..start])))?)
+}
+
+/// Returns the start of the first line that starts at or after `position`.
+pub(crate) fn align(data: &[u8], position: usize) -> usize {
+    if position == 0 || position >= data.len() {
+        return position.min(data.len());
+    }
+    match data[position - 1..].iter().position(|byte| *byte == b'\n') {
+        Some(newline) => position + newline,
+        None => data.len(),
+    }
+}
@@ -0,0 +1,567 @@
+use std::{collections::BTreeMap, io::{self, Write}};
+
+use crate::{Reading, Report, Station, Summary};
+
+/// The layout results are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// `{name=min/mean/max, ...}` like the reference implementation.
+    #[default]
+    Text,
+    /// One JSON object keyed by station name, with the extreme readings if they were kept.
+    Json,
+    /// Comma-separated values with a header row, quoted as in RFC 4180.
+    Csv,
+    /// Tab-separated values with a header row, escaped like PostgreSQL's text format.
+    Tsv,
+}
+
+/// Settings for writing the results of a run.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub format: Format,
+    pub rounding: Rounding,
+    /// Decimal places for CSV and TSV output.
+    pub precision: usize,
+    /// Adds the standard deviation of every station, after the maximum in text output.
+    pub stddev: bool,
+    /// Adds these percentiles and the mode of every station, which need
+    /// [`Options::histograms`](crate::Options::histograms). Unknown values are left out of text
+    /// output, `null` in JSON and empty in CSV and TSV.
+    pub percentiles: Vec<f64>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            rounding: Rounding::default(),
+            precision: 1,
+            stddev: false,
+            percentiles: Vec::new(),
+        }
+    }
+}
+
+/// How values are rounded to one decimal when results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Half up, towards positive infinity, like `Math.round(x * 10.0) / 10.0` in the Java reference implementation.
+    #[default]
+    Reference,
+    /// Half to even, also known as banker's rounding.
+    HalfEven,
+}
+
+impl Rounding {
+    /// Rounds `value` to one decimal.
+    pub fn round(self, value: f64) -> f64 {
+        self.round_to(value, 1)
+    }
+
+    /// Rounds `value` to the given number of decimals.
+    pub fn round_to(self, value: f64, decimals: usize) -> f64 {
+        let scale = 10_f64.powi(decimals.min(i32::MAX as usize) as i32);
+        // with so many decimals that the scaled value overflows, there are none left to round
+        if !(value * scale).is_finite() {
+            return value + 0.0;
+        }
+        let rounded = match self {
+            Rounding::Reference => (value * scale + 0.5).floor() / scale,
+            Rounding::HalfEven => (value * scale).round_ties_even() / scale,
+        };
+        // avoid printing "-0.0"
+        rounded + 0.0
+    }
+
+    /// Rounds the exact fraction `numerator / denominator` of tenths to a whole number of tenths.
+    pub fn round_tenths(self, numerator: i64, denominator: u64) -> i64 {
+        let denominator = denominator as i64;
+        let quotient = numerator.div_euclid(denominator);
+        let remainder = numerator.rem_euclid(denominator);
+        match (2 * remainder).cmp(&denominator) {
+            std::cmp::Ordering::Less => quotient,
+            std::cmp::Ordering::Greater => quotient + 1,
+            std::cmp::Ordering::Equal => match self {
+                Rounding::Reference => quotient + 1,
+                Rounding::HalfEven => quotient + quotient.rem_euclid(2),
+            },
+        }
+    }
+}
+
+/// Writes `results` to `out` in the requested format, followed by a newline.
+pub fn write_results<W: Write>(out: &mut W, results: &BTreeMap<String, Station>, options: &OutputOptions) -> io::Result<()> {
+    match options.format {
+        Format::Text => {
+            write_text(out, results, options)?;
+            writeln!(out)
+        }
+        Format::Json => {
+            write_json(out, results, options)?;
+            writeln!(out)
+        }
+        Format::Csv => write_delimited(out, &[("", results)], false, options, b',', write_csv_field),
+        Format::Tsv => write_delimited(out, &[("", results)], false, options, b'\t', write_tsv_field),
+    }
+}
+
+/// Writes the results of every file in `report.files`, followed by the combined results.
+///
+/// Text output puts every file on its own `path: {...}` line and ends with a `total: {...}` line.
+/// JSON output is one object, `{"files":{"path":{...}},"total":{...}}`. CSV and TSV output get a
+/// leading `file` column, which is empty for the rows of the combined results.
+pub fn write_per_file<W: Write>(out: &mut W, report: &Report, options: &OutputOptions) -> io::Result<()> {
+    let paths: Vec<_> = report.files.iter().map(|file| file.path.to_string_lossy()).collect();
+    match options.format {
+        Format::Text => {
+            for (path, file) in paths.iter().zip(&report.files) {
+                write!(out, "{}: ", path)?;
+                write_text(out, &file.stations, options)?;
+                writeln!(out)?;
+            }
+            write!(out, "total: ")?;
+            write_text(out, &report.stations, options)?;
+            writeln!(out)
+        }
+        Format::Json => {
+            write!(out, "{{\"files\":{{")?;
+            for (i, (path, file)) in paths.iter().zip(&report.files).enumerate() {
+                if i > 0 {
+                    write!(out, ",")?;
+                }
+                write_json_string(out, path)?;
+                write!(out, ":")?;
+                write_json(out, &file.stations, options)?;
+            }
+            write!(out, "}},\"total\":")?;
+            write_json(out, &report.stations, options)?;
+            writeln!(out, "}}")
+        }
+        Format::Csv | Format::Tsv => {
+            let mut sections: Vec<_> = paths.iter().zip(&report.files).map(|(path, file)| (path.as_ref(), &file.stations)).collect();
+            sections.push(("", &report.stations));
+            match options.format {
+                Format::Csv => write_delimited(out, &sections, true, options, b',', write_csv_field),
+                _ => write_delimited(out, &sections, true, options, b'\t', write_tsv_field),
+            }
+        }
+    }
+}
+
+/// Writes `summary` to `out` in the requested format.
+///
+/// Text output has one `name: value` line per value, with a `~` before an estimated number of
+/// stations. JSON output is one object and CSV and TSV output a header row and one row. The
+/// statistics of the readings are left out of text output if there are none, `null` in JSON and
+/// empty in CSV and TSV.
+pub fn write_summary<W: Write>(out: &mut W, summary: &Summary, options: &OutputOptions) -> io::Result<()> {
+    let labels = summary_labels(options);
+    let values = summary_values(summary, options, 1);
+    let (longest, shortest) = (summary.longest.as_deref(), summary.shortest.as_deref());
+    match options.format {
+        Format::Text => {
+            writeln!(out, "rows: {}", summary.rows)?;
+            writeln!(out, "bytes: {}", summary.bytes)?;
+            writeln!(out, "stations: {}{}", if summary.estimated { "~" } else { "" }, summary.stations)?;
+            if let (Some(longest), Some(shortest)) = (longest, shortest) {
+                writeln!(out, "longest: {}", longest)?;
+                writeln!(out, "shortest: {}", shortest)?;
+            }
+            for (label, value) in labels.iter().zip(values) {
+                if let Some(value) = value {
+                    writeln!(out, "{}: {:.1}", label, value)?;
+                }
+            }
+            Ok(())
+        }
+        Format::Json => {
+            write!(out, "{{\"rows\":{},\"bytes\":{},\"stations\":{},\"estimated\":{}", summary.rows, summary.bytes, summary.stations, summary.estimated)?;
+            for (label, name) in [("longest", longest), ("shortest", shortest)] {
+                write!(out, ",\"{}\":", label)?;
+                match name {
+                    Some(name) => write_json_string(out, name)?,
+                    None => write!(out, "null")?,
+                }
+            }
+            for (label, value) in labels.iter().zip(values) {
+                match value {
+                    Some(value) => write!(out, ",\"{}\":{:.1}", label, value)?,
+                    None => write!(out, ",\"{}\":null", label)?,
+                }
+            }
+            writeln!(out, "}}")
+        }
+        Format::Csv => write_summary_row(out, summary, options, b',', write_csv_field),
+        Format::Tsv => write_summary_row(out, summary, options, b'\t', write_tsv_field),
+    }
+}
+
+/// Writes a header row and the row of `summary`, with `write_field` taking care of quoting names.
+fn write_summary_row<W, F>(out: &mut W, summary: &Summary, options: &OutputOptions, delimiter: u8, write_field: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(&mut W, &str) -> io::Result<()>,
+{
+    let delimiter = delimiter as char;
+    let precision = options.precision;
+    write!(out, "rows{0}bytes{0}stations{0}estimated{0}longest{0}shortest", delimiter)?;
+    for label in summary_labels(options) {
+        write!(out, "{}{}", delimiter, label)?;
+    }
+    writeln!(out)?;
+    write!(out, "{1}{0}{2}{0}{3}{0}{4}{0}", delimiter, summary.rows, summary.bytes, summary.stations, summary.estimated)?;
+    write_field(out, summary.longest.as_deref().unwrap_or(""))?;
+    write!(out, "{}", delimiter)?;
+    write_field(out, summary.shortest.as_deref().unwrap_or(""))?;
+    for value in summary_values(summary, options, precision) {
+        write!(out, "{}", delimiter)?;
+        if let Some(value) = value {
+            write!(out, "{:.1$}", value, precision)?;
+        }
+    }
+    writeln!(out)
+}
+
+/// Labels of the statistics of all readings in a summary.
+fn summary_labels(options: &OutputOptions) -> Vec<String> {
+    let mut labels = vec!["min".to_owned(), "mean".to_owned(), "max".to_owned()];
+    if options.stddev {
+        labels.push("stddev".to_owned());
+    }
+    labels.extend(distribution_labels(options));
+    labels
+}
+
+/// The statistics of all readings in `summary`, rounded to `decimals`, in the order of
+/// [`summary_labels`]. They are unknown without readings.
+fn summary_values(summary: &Summary, options: &OutputOptions, decimals: usize) -> Vec<Option<f64>> {
+    let rounding = options.rounding;
+    let overall = &summary.overall;
+    let mut values = Vec::new();
+    if summary.rows > 0 {
+        values.extend([rounding.round_to(overall.min(), decimals), overall.rounded_mean_to(rounding, decimals), rounding.round_to(overall.max(), decimals)].map(Some));
+        if options.stddev {
+            values.push(Some(rounding.round_to(overall.stddev(), decimals)));
+        }
+    } else {
+        values.resize(if options.stddev { 4 } else { 3 }, None);
+    }
+    values.extend(distribution(overall, options).into_iter().map(|value| value.map(|value| rounding.round_to(value, decimals))));
+    values
+}
+
+fn write_text<W: Write>(out: &mut W, results: &BTreeMap<String, Station>, options: &OutputOptions) -> io::Result<()> {
+    let rounding = options.rounding;
+    let labels = distribution_labels(options);
+    write!(out, "{{")?;
+    for (i, (name, station)) in results.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "{}={}", name, station.display(rounding))?;
+        if options.stddev {
+            write!(out, "/{:.1}", rounding.round(station.stddev()))?;
+        }
+        for (label, value) in labels.iter().zip(distribution(station, options)) {
+            if let Some(value) = value {
+                write!(out, " {}={:.1}", label, rounding.round(value))?;
+            }
+        }
+    }
+    write!(out, "}}")
+}
+
+fn write_json<W: Write>(out: &mut W, results: &BTreeMap<String, Station>, options: &OutputOptions) -> io::Result<()> {
+    let rounding = options.rounding;
+    let labels = distribution_labels(options);
+    write!(out, "{{")?;
+    for (i, (name, station)) in results.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_json_string(out, name)?;
+        write!(
+            out,
+            ":{{\"min\":{:.1},\"mean\":{:.1},\"max\":{:.1},\"count\":{},\"sum\":",
+            rounding.round(station.min()),
+            station.rounded_mean(rounding),
+            rounding.round(station.max()),
+            station.count(),
+        )?;
+        match station.sum_tenths() {
+            Some(sum_tenths) => write!(out, "{:.1}", sum_tenths as f64 / 10.0)?,
+            None => write!(out, "{}", station.sum())?,
+        }
+        if options.stddev {
+            write!(out, ",\"stddev\":{:.1}", rounding.round(station.stddev()))?;
+        }
+        for (label, value) in labels.iter().zip(distribution(station, options)) {
+            match value {
+                Some(value) => write!(out, ",\"{}\":{:.1}", label, rounding.round(value))?,
+                None => write!(out, ",\"{}\":null", label)?,
+            }
+        }
+        if let Some(extremes) = station.extremes() {
+            write!(out, ",\"highest\":")?;
+            write_readings(out, extremes.highest())?;
+            write!(out, ",\"lowest\":")?;
+            write_readings(out, extremes.lowest())?;
+        }
+        write!(out, "}}")?;
+    }
+    write!(out, "}}")
+}
+
+/// Labels of the requested percentiles, like `p95`, followed by the mode.
+fn distribution_labels(options: &OutputOptions) -> Vec<String> {
+    if options.percentiles.is_empty() {
+        return Vec::new();
+    }
+    let mut labels: Vec<_> = options.percentiles.iter().map(|percentile| format!("p{}", percentile)).collect();
+    labels.push("mode".to_owned());
+    labels
+}
+
+/// The requested percentiles of `station` followed by the mode, in the order of [`distribution_labels`].
+fn distribution(station: &Station, options: &OutputOptions) -> Vec<Option<f64>> {
+    if options.percentiles.is_empty() {
+        return Vec::new();
+    }
+    let mut values: Vec<_> = options.percentiles.iter().map(|percentile| station.percentile(*percentile)).collect();
+    values.push(station.mode());
+    values
+}
+
+/// Writes `readings` as a JSON array of `{"value":...,"offset":...}` objects, with a `"file"` if known.
+fn write_readings<W: Write>(out: &mut W, readings: &[Reading]) -> io::Result<()> {
+    write!(out, "[")?;
+    for (i, reading) in readings.iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        // readings are written as they were parsed, without rounding
+        write!(out, "{{\"value\":{:?},\"offset\":{}", reading.value + 0.0, reading.offset)?;
+        if let Some(file) = &reading.file {
+            write!(out, ",\"file\":")?;
+            write_json_string(out, &file.to_string_lossy())?;
+        }
+        write!(out, "}}")?;
+    }
+    write!(out, "]")
+}
+
+/// Writes `value` as a quoted JSON string.
+fn write_json_string<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    write!(out, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+/// Writes a header row and one row per station of every section, with `write_field` taking care
+/// of quoting names. With `with_file`, every row starts with the name of its section.
+fn write_delimited<W, F>(out: &mut W, sections: &[(&str, &BTreeMap<String, Station>)], with_file: bool, options: &OutputOptions, delimiter: u8, write_field: F) -> io::Result<()>
+where
+    W: Write,
+    F: Fn(&mut W, &str) -> io::Result<()>,
+{
+    let delimiter = delimiter as char;
+    let precision = options.precision;
+    let rounding = options.rounding;
+    if with_file {
+        write!(out, "file{}", delimiter)?;
+    }
+    write!(out, "station{0}min{0}mean{0}max{0}count", delimiter)?;
+    if options.stddev {
+        write!(out, "{}stddev", delimiter)?;
+    }
+    for label in distribution_labels(options) {
+        write!(out, "{}{}", delimiter, label)?;
+    }
+    writeln!(out)?;
+    for (file, results) in sections {
+        for (name, station) in *results {
+            if with_file {
+                write_field(out, file)?;
+                write!(out, "{}", delimiter)?;
+            }
+            write_field(out, name)?;
+            write!(
+                out,
+                "{0}{1:.4$}{0}{2:.4$}{0}{3:.4$}{0}{5}",
+                delimiter,
+                rounding.round_to(station.min(), precision),
+                station.rounded_mean_to(rounding, precision),
+                rounding.round_to(station.max(), precision),
+                precision,
+                station.count(),
+            )?;
+            if options.stddev {
+                write!(out, "{}{:.2$}", delimiter, rounding.round_to(station.stddev(), precision), precision)?;
+            }
+            for value in distribution(station, options) {
+                write!(out, "{}", delimiter)?;
+                if let Some(value) = value {
+                    write!(out, "{:.1$}", rounding.round_to(value, precision), precision)?;
+                }
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `value` as a CSV field, quoting it if it contains a delimiter, quote or line break.
+fn write_csv_field<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    if value.contains([',', '"', '\r', '\n']) {
+        write!(out, "\"{}\"", value.replace('"', "\"\""))
+    } else {
+        write!(out, "{}", value)
+    }
+}
+
+/// Writes `value` as a TSV field, escaping backslashes, tabs and line breaks.
+fn write_tsv_field<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    for c in value.chars() {
+        match c {
+            '\\' => write!(out, "\\\\")?,
+            '\t' => write!(out, "\\t")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FixedStation;
+
+    /// Formats like the writers do.
+    fn one_decimal(value: f64) -> String {
+        format!("{:.1}", value)
+    }
+
+    #[test]
+    fn ties_round_up_or_to_even() {
+        for (value, reference, half_even) in [(0.25, "0.3", "0.2"), (0.75, "0.8", "0.8"), (-0.25, "-0.2", "-0.2"), (-0.75, "-0.7", "-0.8"), (1.0, "1.0", "1.0")] {
+            assert_eq!(one_decimal(Rounding::Reference.round(value)), reference, "{}", value);
+            assert_eq!(one_decimal(Rounding::HalfEven.round(value)), half_even, "{}", value);
+        }
+        assert_eq!(Rounding::Reference.round_to(1.125, 2), 1.13);
+        assert_eq!(Rounding::HalfEven.round_to(1.125, 2), 1.12);
+    }
+
+    #[test]
+    fn exact_means_round_up_or_to_even() {
+        // (sum of tenths, readings, reference, half-even)
+        for (numerator, denominator, reference, half_even) in [(5, 2, 3, 2), (7, 2, 4, 4), (-5, 2, -2, -2), (-3, 2, -1, -2), (-1, 2, 0, 0), (10, 3, 3, 3), (-10, 3, -3, -3), (0, 1, 0, 0)] {
+            assert_eq!(Rounding::Reference.round_tenths(numerator, denominator), reference, "{}/{}", numerator, denominator);
+            assert_eq!(Rounding::HalfEven.round_tenths(numerator, denominator), half_even, "{}/{}", numerator, denominator);
+        }
+    }
+
+    /// A station with the single reading `tenths`.
+    fn station(tenths: i16) -> Station {
+        let mut station = FixedStation::default();
+        station.update(tenths);
+        Station::from(station)
+    }
+
+    fn written(results: &BTreeMap<String, Station>, options: &OutputOptions) -> String {
+        let mut out = Vec::new();
+        write_results(&mut out, results, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let escaped = |value: &str| {
+            let mut out = Vec::new();
+            write_json_string(&mut out, value).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(escaped("St. John's, a=b"), "\"St. John's, a=b\"");
+        assert_eq!(escaped("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(escaped("C:\\temp"), "\"C:\\\\temp\"");
+        assert_eq!(escaped("a\nb\rc\td\u{1}e\u{1f}f\u{7f}"), "\"a\\nb\\rc\\td\\u0001e\\u001ff\u{7f}\"");
+        assert_eq!(escaped("Zürich"), "\"Zürich\"");
+
+        let results = BTreeMap::from([("a,b=c".to_owned(), station(10)), ("q\"\\\n".to_owned(), station(-5))]);
+        let json = OutputOptions { format: Format::Json, ..OutputOptions::default() };
+        assert_eq!(
+            written(&results, &json),
+            "{\"a,b=c\":{\"min\":1.0,\"mean\":1.0,\"max\":1.0,\"count\":1,\"sum\":1.0},\"q\\\"\\\\\\n\":{\"min\":-0.5,\"mean\":-0.5,\"max\":-0.5,\"count\":1,\"sum\":-0.5}}\n"
+        );
+    }
+
+    #[test]
+    fn delimited_fields_are_quoted_or_escaped() {
+        let results = BTreeMap::from([
+            ("a,b".to_owned(), station(10)),
+            ("back\\slash".to_owned(), station(0)),
+            ("line\nbreak".to_owned(), station(123)),
+            ("say \"hi\"".to_owned(), station(-5)),
+            ("tab\there".to_owned(), station(999)),
+        ]);
+        assert_eq!(
+            written(&results, &OutputOptions { format: Format::Csv, precision: 3, ..OutputOptions::default() }),
+            "station,min,mean,max,count\n\
+             \"a,b\",1.000,1.000,1.000,1\n\
+             back\\slash,0.000,0.000,0.000,1\n\
+             \"line\nbreak\",12.300,12.300,12.300,1\n\
+             \"say \"\"hi\"\"\",-0.500,-0.500,-0.500,1\n\
+             tab\there,99.900,99.900,99.900,1\n"
+        );
+        assert_eq!(
+            written(&results, &OutputOptions { format: Format::Tsv, precision: 0, ..OutputOptions::default() }),
+            "station\tmin\tmean\tmax\tcount\n\
+             a,b\t1\t1\t1\t1\n\
+             back\\\\slash\t0\t0\t0\t1\n\
+             line\\nbreak\t12\t12\t12\t1\n\
+             say \"hi\"\t0\t0\t0\t1\n\
+             tab\\there\t100\t100\t100\t1\n"
+        );
+        // with more decimals than the readings have, means aren't cut to tenths
+        let mut results = BTreeMap::from([("x".to_owned(), station(1))]);
+        results.get_mut("x").unwrap().merge(station(2));
+        assert_eq!(written(&results, &OutputOptions { format: Format::Csv, precision: 2, ..OutputOptions::default() }), "station,min,mean,max,count\nx,0.10,0.15,0.20,2\n");
+        // a precision beyond the range of `f64` prints the values as they are
+        let csv = written(&results, &OutputOptions { format: Format::Csv, precision: 400, ..OutputOptions::default() });
+        let fields: Vec<f64> = csv.lines().nth(1).unwrap().split(',').skip(1).map(|field| field.parse().unwrap()).collect();
+        assert_eq!(fields, [0.1, 0.15, 0.2, 2.0]);
+        for rounding in [Rounding::Reference, Rounding::HalfEven] {
+            assert_eq!(rounding.round_to(1e300, 20), 1e300);
+            assert!(rounding.round_to(-0.0, usize::MAX).is_sign_positive());
+        }
+    }
+
+    #[test]
+    fn negative_zero_is_printed_as_zero() {
+        for rounding in [Rounding::Reference, Rounding::HalfEven] {
+            assert_eq!(one_decimal(rounding.round(-0.04)), "0.0");
+            assert_eq!(one_decimal(rounding.round(-0.0)), "0.0");
+            assert_eq!(format!("{:.2}", rounding.round_to(-0.004, 2)), "0.00");
+
+            // a mean of -0.05 from an exact sum of -1 tenth over 2 readings
+            let mut station = FixedStation::default();
+            station.update(-1);
+            station.update(0);
+            let station = Station::from(station);
+            assert_eq!(one_decimal(station.rounded_mean(rounding)), "0.0");
+            assert_eq!(station.display(rounding).to_string(), "-0.1/0.0/0.0");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+/// How the lines of the input are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputFormat {
+    /// Separates the station name from the temperature.
+    pub delimiter: u8,
+    /// Separates the integer part of a temperature from its fraction, `.` or `,`.
+    pub decimal_separator: u8,
+    pub line_ending: LineEnding,
+}
+
+impl Default for InputFormat {
+    fn default() -> Self {
+        Self { delimiter: b';', decimal_separator: b'.', line_ending: LineEnding::default() }
+    }
+}
+
+/// What ends a line. Chunks are always split after a `\n`, so both keep every line in one chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    /// `\r\n`. A line that ends in a bare `\n` is accepted as well.
+    CrLf,
+}
+
+/// Parses a temperature in the 1BRC format (`-99.9` to `99.9`, exactly one fractional digit)
+/// into tenths of a degree.
+///
+/// Returns `None` for anything else, without going through UTF-8 validation or float parsing.
+pub fn parse_tenths(bytes: &[u8]) -> Option<i16> {
+    match parse_tenths_prefix(bytes, b'.') {
+        Some((value, length)) if length == bytes.len() => Some(value),
+        _ => None,
+    }
+}
+
+/// Parses a 1BRC temperature at the start of `bytes`, with `separator` between the ones and the
+/// tenths, returning it with the number of bytes it took.
+///
+/// Whatever follows the temperature is left for the caller to check.
+pub(crate) fn parse_tenths_prefix(bytes: &[u8], separator: u8) -> Option<(i16, usize)> {
+    let (negative, digits) = match bytes {
+        [b'-', rest @ ..] => (true, rest),
+        _ => (false, bytes),
+    };
+    let (value, length) = match *digits {
+        [ones, dot, tenths, ..] if dot == separator && ones.is_ascii_digit() && tenths.is_ascii_digit() => {
+            ((ones - b'0') as i16 * 10 + (tenths - b'0') as i16, 3)
+        }
+        [tens, ones, dot, tenths, ..] if dot == separator && tens.is_ascii_digit() && ones.is_ascii_digit() && tenths.is_ascii_digit() => {
+            ((tens - b'0') as i16 * 100 + (ones - b'0') as i16 * 10 + (tenths - b'0') as i16, 4)
+        }
+        _ => return None,
+    };
+    Some((if negative { -value } else { value }, length + negative as usize))
+}
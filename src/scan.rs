@@ -0,0 +1,138 @@
+//! Finding the field delimiter (`;` by default) and the `\n` that end the parts of a line.
+//!
+//! The vectorized scanners look for both delimiters at once, 8 (SWAR), 16 (SSE2) or 32 (AVX2)
+//! bytes at a time. The fastest one the CPU supports is picked at runtime; the scalar scanner is
+//! kept as a simple reference for debugging.
+
+/// Which implementation is used to scan for delimiters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scanner {
+    /// One byte at a time.
+    Scalar,
+    /// Eight bytes at a time in a `u64`, portable to every target.
+    Swar,
+    /// 16 bytes at a time with SSE2.
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    /// 32 bytes at a time with AVX2.
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Scanner {
+    /// The fastest scanner the current CPU supports.
+    pub(crate) fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Scanner::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Scanner::Sse2;
+            }
+        }
+        Scanner::Swar
+    }
+}
+
+/// A scanner that finds the next `delimiter` or `\n` at or after a position, or the end of the data.
+pub(crate) trait FindDelimiter {
+    fn find(data: &[u8], from: usize, delimiter: u8) -> usize;
+}
+
+pub(crate) struct ScalarScan;
+
+impl FindDelimiter for ScalarScan {
+    #[inline]
+    fn find(data: &[u8], from: usize, delimiter: u8) -> usize {
+        find_scalar(data, from, delimiter)
+    }
+}
+
+fn find_scalar(data: &[u8], from: usize, delimiter: u8) -> usize {
+    data[from..].iter().position(|byte| *byte == delimiter || *byte == b'\n').map_or(data.len(), |idx| from + idx)
+}
+
+pub(crate) struct SwarScan;
+
+impl FindDelimiter for SwarScan {
+    #[inline]
+    fn find(data: &[u8], from: usize, delimiter: u8) -> usize {
+        const ONES: u64 = 0x0101_0101_0101_0101;
+        const HIGH: u64 = 0x8080_8080_8080_8080;
+        let mut i = from;
+        while i + 8 <= data.len() {
+            let word = u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+            let delimiters = word ^ (ONES * delimiter as u64);
+            let newlines = word ^ (ONES * b'\n' as u64);
+            // the lowest set high bit marks the first zero byte exactly
+            let found = ((delimiters.wrapping_sub(ONES) & !delimiters) | (newlines.wrapping_sub(ONES) & !newlines)) & HIGH;
+            if found != 0 {
+                return i + found.trailing_zeros() as usize / 8;
+            }
+            i += 8;
+        }
+        find_scalar(data, i, delimiter)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct Sse2Scan;
+
+#[cfg(target_arch = "x86_64")]
+impl FindDelimiter for Sse2Scan {
+    #[inline]
+    fn find(data: &[u8], from: usize, delimiter: u8) -> usize {
+        use std::arch::x86_64::*;
+
+        let mut i = from;
+        // SAFETY: SSE2 is part of the x86_64 baseline, and every load stays within `data`.
+        unsafe {
+            let delimiters = _mm_set1_epi8(delimiter as i8);
+            let newlines = _mm_set1_epi8(b'\n' as i8);
+            while i + 16 <= data.len() {
+                let chunk = _mm_loadu_si128(data.as_ptr().add(i) as *const __m128i);
+                let found = _mm_or_si128(_mm_cmpeq_epi8(chunk, delimiters), _mm_cmpeq_epi8(chunk, newlines));
+                let mask = _mm_movemask_epi8(found) as u32;
+                if mask != 0 {
+                    return i + mask.trailing_zeros() as usize;
+                }
+                i += 16;
+            }
+        }
+        find_scalar(data, i, delimiter)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct Avx2Scan;
+
+#[cfg(target_arch = "x86_64")]
+impl FindDelimiter for Avx2Scan {
+    #[inline]
+    fn find(data: &[u8], from: usize, delimiter: u8) -> usize {
+        // SAFETY: this scanner is only selected after `is_x86_feature_detected!("avx2")`.
+        unsafe { find_avx2(data, from, delimiter) }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn find_avx2(data: &[u8], from: usize, delimiter: u8) -> usize {
+    use std::arch::x86_64::*;
+
+    let mut i = from;
+    let delimiters = _mm256_set1_epi8(delimiter as i8);
+    let newlines = _mm256_set1_epi8(b'\n' as i8);
+    while i + 32 <= data.len() {
+        // SAFETY: the load stays within `data`
+        let chunk = unsafe { _mm256_loadu_si256(data.as_ptr().add(i) as *const __m256i) };
+        let found = _mm256_or_si256(_mm256_cmpeq_epi8(chunk, delimiters), _mm256_cmpeq_epi8(chunk, newlines));
+        let mask = _mm256_movemask_epi8(found) as u32;
+        if mask != 0 {
+            return i + mask.trailing_zeros() as usize;
+        }
+        i += 32;
+    }
+    find_scalar(data, i, delimiter)
+}
@@ -0,0 +1,124 @@
+use std::{ops::Range, sync::atomic::{AtomicUsize, Ordering}, thread, time::{Duration, Instant}};
+
+use crate::{Error, Options};
+
+/// How the input is divided between the worker threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Chunking {
+    /// One equally sized range per thread.
+    PerThread,
+    /// Many chunks of `Options::chunk_size` bytes that idle workers keep claiming through a shared
+    /// cursor, so a slow thread doesn't hold up the others.
+    #[default]
+    Queue,
+}
+
+/// How much time a worker thread spent processing chunks and how long it waited for the others.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Number of chunks the thread processed.
+    pub chunks: u64,
+    pub busy: Duration,
+    pub idle: Duration,
+}
+
+/// Runs `work` on newline-aligned ranges of the bytes in `input` and returns the state of each
+/// worker thread.
+///
+/// `align` maps a byte position to the start of the first line that starts at or after it, so
+/// both neighbours of a boundary agree on where it is without any upfront pass over the input.
+pub(crate) fn run<S, A, F>(input: Range<usize>, options: &Options, align: A, work: F) -> Result<Vec<(S, ThreadStats)>, Error>
+where
+    S: Default + Send,
+    A: Fn(usize) -> Result<usize, Error> + Sync,
+    F: Fn(&mut S, Range<usize>) -> Result<(), Error> + Sync,
+{
+    let threads = options.threads.max(1);
+    let length = input.end;
+    let chunk_size = match options.chunking {
+        Chunking::PerThread => input.len().div_ceil(threads),
+        Chunking::Queue => options.chunk_size,
+    }
+    .max(1);
+    let cursor = AtomicUsize::new(input.start);
+    // start of the first chunk that failed, later chunks are skipped
+    let failed_at = AtomicUsize::new(usize::MAX);
+    let started = Instant::now();
+
+    // Use scoped threads to keep things simpler
+    let results: Result<Vec<_>, Error> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let (cursor, failed_at, align, work) = (&cursor, &failed_at, &align, &work);
+                scope.spawn(move || -> Result<(S, ThreadStats), (usize, Error)> {
+                    let mut state = S::default();
+                    let mut stats = ThreadStats::default();
+                    loop {
+                        let start = match options.chunking {
+                            Chunking::PerThread if stats.chunks > 0 => break,
+                            Chunking::PerThread => input.start + i * chunk_size,
+                            Chunking::Queue => cursor.fetch_add(chunk_size, Ordering::Relaxed),
+                        };
+                        if start >= length || start > failed_at.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        let chunk_started = Instant::now();
+                        let range = align(start).and_then(|aligned| Ok(aligned..align((start + chunk_size).min(length))?));
+                        if let Err(err) = range.and_then(|range| if range.is_empty() { Ok(()) } else { work(&mut state, range) }) {
+                            // no point in processing the rest, but earlier chunks may still hold the first error
+                            failed_at.fetch_min(start, Ordering::Relaxed);
+                            return Err((start, err));
+                        }
+                        stats.busy += chunk_started.elapsed();
+                        stats.chunks += 1;
+                    }
+                    Ok((state, stats))
+                })
+            })
+            .collect();
+        let mut results = Vec::with_capacity(threads);
+        // the error of the chunk that starts first, which also orders errors in different files
+        let mut error: Option<(usize, Error)> = None;
+        for handle in handles {
+            match handle.join().expect("Worker thread panicked") {
+                Ok(result) => results.push(result),
+                Err((start, err)) => {
+                    if error.as_ref().is_none_or(|(first, _)| start < *first) {
+                        error = Some((start, err));
+                    }
+                }
+            }
+        }
+        error.map_or(Ok(results), |(_, err)| Err(err))
+    });
+
+    let elapsed = started.elapsed();
+    let mut results = results?;
+    for (_, stats) in &mut results {
+        stats.idle = elapsed.saturating_sub(stats.busy);
+    }
+    Ok(results)
+}
+
+/// Merges `items` pairwise in parallel until one is left.
+pub(crate) fn reduce<T, F>(mut items: Vec<T>, merge: F) -> Option<T>
+where
+    T: Send,
+    F: Fn(&mut T, T) + Sync,
+{
+    let merge = &merge;
+    while items.len() > 1 {
+        let odd = match items.len() % 2 {
+            1 => items.pop(),
+            _ => None,
+        };
+        let right = items.split_off(items.len() / 2);
+        thread::scope(|scope| {
+            for (left, right) in items.iter_mut().zip(right) {
+                scope.spawn(move || merge(left, right));
+            }
+        });
+        items.extend(odd);
+    }
+    items.pop()
+}
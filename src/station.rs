@@ -0,0 +1,326 @@
+use std::{fmt::{Debug, Display}, path::Path, sync::Arc};
+
+use crate::{extremes::ExtremesStation, histogram::HistogramStation, tdigest::SketchStation, Extremes, Histogram, Rounding, TDigest};
+
+/// Running statistics for a single weather station.
+#[derive(Clone, PartialEq)]
+pub struct Station {
+    min: f64,
+    max: f64,
+    sum: f64,
+    values_read: u64,
+    /// The exact sum in tenths, as long as every reading came from the fixed-point parser.
+    sum_tenths: Option<i64>,
+    /// The exact sum of the squared readings in hundredths, alongside `sum_tenths`.
+    sum_squares: Option<i64>,
+    /// Mean and sum of squared deviations from it, updated with Welford's algorithm once the
+    /// sums aren't exact anymore.
+    running_mean: f64,
+    squared_deviations: f64,
+    /// Every reading, if histograms are kept and every reading came from the fixed-point parser.
+    histogram: Option<Box<Histogram>>,
+    /// A summary of every reading, if sketches are kept.
+    sketch: Option<Box<TDigest>>,
+    /// The highest and lowest readings with their positions, if they are kept.
+    extremes: Option<Box<Extremes>>,
+}
+
+impl Default for Station {
+    fn default() -> Self {
+        Self {
+            min: f64::MAX,
+            max: f64::MIN,
+            sum: 0.0,
+            values_read: 0,
+            sum_tenths: Some(0),
+            sum_squares: Some(0),
+            running_mean: 0.0,
+            squared_deviations: 0.0,
+            histogram: None,
+            sketch: None,
+            extremes: None,
+        }
+    }
+}
+
+impl Station {
+    pub fn update(&mut self, value: f64) {
+        (self.running_mean, self.squared_deviations) = self.deviations();
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.values_read += 1;
+        self.sum_tenths = None;
+        self.sum_squares = None;
+        self.histogram = None;
+        let delta = value - self.running_mean;
+        self.running_mean += delta / self.values_read as f64;
+        self.squared_deviations += delta * (value - self.running_mean);
+    }
+    
+    pub fn merge(&mut self, mut other: Station) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.histogram = match (self.histogram.take(), other.histogram.take()) {
+            (Some(mut histogram), Some(other)) => {
+                histogram.merge(&other);
+                Some(histogram)
+            }
+            // an empty station has nothing to count
+            (histogram, other) if self.values_read == 0 => other.or(histogram),
+            (histogram, _) if other.values_read == 0 => histogram,
+            _ => None,
+        };
+        self.sketch = match (self.sketch.take(), other.sketch.take()) {
+            (Some(mut sketch), Some(other)) => {
+                sketch.merge(&other);
+                Some(sketch)
+            }
+            (sketch, other) if self.values_read == 0 => other.or(sketch),
+            (sketch, _) if other.values_read == 0 => sketch,
+            _ => None,
+        };
+        self.extremes = match (self.extremes.take(), other.extremes.take()) {
+            (Some(mut extremes), Some(other)) => {
+                extremes.merge(&other);
+                Some(extremes)
+            }
+            (extremes, other) => extremes.or(other),
+        };
+        if let (Some(sum_tenths), Some(sum_squares), Some(other_sum_tenths), Some(other_sum_squares)) = (self.sum_tenths, self.sum_squares, other.sum_tenths, other.sum_squares) {
+            self.values_read += other.values_read;
+            self.sum_tenths = Some(sum_tenths + other_sum_tenths);
+            self.sum_squares = Some(sum_squares + other_sum_squares);
+            return;
+        }
+        // Chan et al.'s formula for combining the deviations of two parts
+        let (mean, squared_deviations) = self.deviations();
+        let (other_mean, other_squared_deviations) = other.deviations();
+        let (count, other_count) = (self.values_read as f64, other.values_read as f64);
+        self.values_read += other.values_read;
+        self.sum_tenths = None;
+        self.sum_squares = None;
+        if self.values_read > 0 {
+            let total = self.values_read as f64;
+            let delta = other_mean - mean;
+            self.running_mean = mean + delta * other_count / total;
+            self.squared_deviations = squared_deviations + other_squared_deviations + delta * delta * count * other_count / total;
+        }
+    }
+    
+    /// The mean and the sum of squared deviations from it, computed from the exact sums where available.
+    fn deviations(&self) -> (f64, f64) {
+        match (self.sum_tenths, self.sum_squares) {
+            (Some(sum_tenths), Some(sum_squares)) if self.values_read > 0 => {
+                // (n * Σx² - (Σx)²) / n, in integers
+                let count = self.values_read as i128;
+                let squared_deviations = (count * sum_squares as i128 - sum_tenths as i128 * sum_tenths as i128) as f64 / count as f64 / 100.0;
+                (self.mean(), squared_deviations)
+            }
+            (Some(_), Some(_)) => (0.0, 0.0),
+            _ => (self.running_mean, self.squared_deviations),
+        }
+    }
+    
+    pub fn min(&self) -> f64 {
+        self.min
+    }
+    
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+    
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+    
+    pub fn count(&self) -> u64 {
+        self.values_read
+    }
+    
+    /// Sum of all readings in tenths of a degree, if it is known exactly.
+    pub fn sum_tenths(&self) -> Option<i64> {
+        self.sum_tenths
+    }
+    
+    pub fn mean(&self) -> f64 {
+        match self.sum_tenths {
+            Some(sum_tenths) => sum_tenths as f64 / 10.0 / self.values_read as f64,
+            None => self.sum / self.values_read as f64,
+        }
+    }
+    
+    /// Population variance of the readings.
+    pub fn variance(&self) -> f64 {
+        self.deviations().1 / self.values_read as f64
+    }
+    
+    /// Population standard deviation of the readings.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+    
+    /// Counts of every reading, if [`Options::histograms`](crate::Options::histograms) is set.
+    pub fn histogram(&self) -> Option<&Histogram> {
+        self.histogram.as_deref()
+    }
+    
+    /// A summary of every reading, if [`Options::sketch`](crate::Options::sketch) is set.
+    pub fn sketch(&self) -> Option<&TDigest> {
+        self.sketch.as_deref()
+    }
+    
+    /// The highest and lowest readings, if [`Options::extremes`](crate::Options::extremes) is set.
+    pub fn extremes(&self) -> Option<&Extremes> {
+        self.extremes.as_deref()
+    }
+    
+    /// Marks the extreme readings as found in `file`.
+    pub(crate) fn set_file(&mut self, file: &Arc<Path>) {
+        if let Some(extremes) = &mut self.extremes {
+            extremes.set_file(file);
+        }
+    }
+    
+    /// The reading that `percentile` percent of all readings are less than or equal to, exactly
+    /// from the histogram or estimated from the sketch, if either is kept.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        match (&self.histogram, &self.sketch) {
+            (Some(histogram), _) => histogram.percentile(percentile),
+            (None, Some(sketch)) => sketch.percentile(percentile),
+            (None, None) => None,
+        }
+    }
+    
+    /// The most frequent reading, if it is known.
+    pub fn mode(&self) -> Option<f64> {
+        self.histogram.as_ref()?.mode()
+    }
+    
+    /// The mean rounded to one decimal, computed from the exact sum where available.
+    pub fn rounded_mean(&self, rounding: Rounding) -> f64 {
+        self.rounded_mean_to(rounding, 1)
+    }
+    
+    /// The mean rounded to the given number of decimals.
+    pub fn rounded_mean_to(&self, rounding: Rounding, decimals: usize) -> f64 {
+        match self.sum_tenths {
+            Some(sum_tenths) if decimals == 1 => rounding.round_tenths(sum_tenths, self.values_read) as f64 / 10.0 + 0.0,
+            _ => rounding.round_to(self.mean(), decimals),
+        }
+    }
+    
+    /// Formats the station as `min/mean/max` with the given rounding.
+    pub fn display(&self, rounding: Rounding) -> impl Display + '_ {
+        StationDisplay { station: self, rounding }
+    }
+}
+
+impl From<FixedStation> for Station {
+    fn from(fixed: FixedStation) -> Self {
+        Self {
+            min: fixed.min as f64 / 10.0,
+            max: fixed.max as f64 / 10.0,
+            sum: fixed.sum as f64 / 10.0,
+            values_read: fixed.values_read,
+            sum_tenths: Some(fixed.sum),
+            sum_squares: Some(fixed.sum_squares),
+            running_mean: 0.0,
+            squared_deviations: 0.0,
+            histogram: None,
+            sketch: None,
+            extremes: None,
+        }
+    }
+}
+
+impl From<HistogramStation> for Station {
+    fn from(station: HistogramStation) -> Self {
+        Self { histogram: station.histogram, ..station.station.into() }
+    }
+}
+
+impl<S: Into<Station>> From<ExtremesStation<S>> for Station {
+    fn from(station: ExtremesStation<S>) -> Self {
+        Self { extremes: station.extremes, ..station.station.into() }
+    }
+}
+
+impl From<SketchStation> for Station {
+    fn from(station: SketchStation) -> Self {
+        Self { sketch: station.sketch, ..station.station }
+    }
+}
+
+/// Running statistics for a single weather station, kept in tenths of a degree.
+///
+/// Used for 1BRC-formatted input, where the integer sum is exact no matter how many rows are read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FixedStation {
+    min: i16,
+    max: i16,
+    sum: i64,
+    /// Sum of the squared readings, for the variance.
+    sum_squares: i64,
+    values_read: u64,
+}
+
+impl Default for FixedStation {
+    fn default() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            sum_squares: 0,
+            values_read: 0
+        }
+    }
+}
+
+impl FixedStation {
+    pub fn update(&mut self, tenths: i16) {
+        self.min = self.min.min(tenths);
+        self.max = self.max.max(tenths);
+        self.sum += tenths as i64;
+        self.sum_squares += tenths as i64 * tenths as i64;
+        self.values_read += 1;
+    }
+    
+    pub fn merge(&mut self, other: FixedStation) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        self.values_read += other.values_read;
+    }
+    
+    /// Sum of all readings in tenths of a degree.
+    pub fn sum_tenths(&self) -> i64 {
+        self.sum
+    }
+}
+
+impl Debug for Station {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Station").field("min", &self.min).field("max", &self.max).field("sum", &self.sum).field("values_read", &self.values_read).field("sum_tenths", &self.sum_tenths).field("sum_squares", &self.sum_squares).field("running_mean", &self.running_mean).field("squared_deviations", &self.squared_deviations).field("histogram", &self.histogram).field("sketch", &self.sketch).field("extremes", &self.extremes).finish()
+    }
+}
+
+impl Display for Station {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display(Rounding::default()))
+    }
+}
+
+struct StationDisplay<'a> {
+    station: &'a Station,
+    rounding: Rounding,
+}
+
+impl Display for StationDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rounding = self.rounding;
+        write!(f, "{:.1}/{:.1}/{:.1}", rounding.round(self.station.min), self.station.rounded_mean(rounding), rounding.round(self.station.max))
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, io::{ErrorKind, Read}, sync::{atomic::{AtomicBool, Ordering}, mpsc::sync_channel, Mutex}, thread, time::Instant};
+
+use crate::{compressed, count_lines, finish, process_chunk, schedule, summary::StreamSummary, ChunkResults, Error, Options, Partial, Report, Station, Summary, ThreadStats};
+
+/// A newline-aligned piece of the stream and where it starts.
+struct Block {
+    data: Vec<u8>,
+    offset: u64,
+    first_line: u64,
+}
+
+/// Aggregates measurements from an arbitrary reader, e.g. stdin or a pipe.
+///
+/// The stream is cut into newline-aligned blocks of roughly `options.block_size` bytes,
+/// which are handed to the worker threads over a bounded channel.
+pub fn aggregate_reader<R: Read>(reader: R, options: &Options) -> Result<BTreeMap<String, Station>, Error> {
+    Ok(aggregate_reader_report(reader, options)?.stations)
+}
+
+/// Like [`aggregate_reader`], but also reports the malformed lines skipped in lenient mode.
+///
+/// Gzip and zstd compressed streams are recognized by their magic bytes and decoded on the
+/// calling thread.
+pub fn aggregate_reader_report<R: Read>(reader: R, options: &Options) -> Result<Report, Error> {
+    options.validate()?;
+    let partials = aggregate_blocks(compressed::decoder(reader)?, options, Partial::accumulate)?;
+    Ok(finish(partials))
+}
+
+/// Like [`aggregate_reader_report`], but only sums up the stations, estimating how many distinct
+/// ones there are with a [`HyperLogLog`](crate::HyperLogLog) of [`Options::distinct_precision`].
+///
+/// No station is kept beyond the block it is found in, so memory use doesn't grow with the number
+/// of stations.
+pub fn aggregate_reader_summary<R: Read>(reader: R, options: &Options) -> Result<Summary, Error> {
+    options.validate()?;
+    if !(4..=18).contains(&options.distinct_precision) {
+        return Err(Error::InvalidOption("the distinct precision must be from 4 to 18"));
+    }
+    let partials = aggregate_blocks(compressed::decoder(reader)?, options, |summary: &mut StreamSummary, chunk| summary.accumulate(chunk, options.distinct_precision))?;
+    let summaries = partials.into_iter().map(|(summary, _)| summary).collect();
+    Ok(schedule::reduce(summaries, StreamSummary::merge).unwrap_or_default().finish())
+}
+
+/// Cuts the stream into blocks and hands them to the worker threads, which `accumulate` the
+/// results of every block they process.
+fn aggregate_blocks<R, P, A>(mut reader: R, options: &Options, accumulate: A) -> Result<Vec<(P, ThreadStats)>, Error>
+where
+    R: Read,
+    P: Default + Send,
+    A: Fn(&mut P, ChunkResults) + Sync,
+{
+    let threads = options.threads.max(1);
+    let block_size = options.block_size.max(1);
+    let (sender, receiver) = sync_channel::<Block>(threads * 2);
+    let receiver = Mutex::new(receiver);
+    let failed = AtomicBool::new(false);
+    let started = Instant::now();
+
+    thread::scope(|scope| {
+        let mut handles = Vec::with_capacity(threads);
+        for _i in 0..threads {
+            let receiver = &receiver;
+            let failed = &failed;
+            let accumulate = &accumulate;
+            handles.push(scope.spawn(move || -> Result<(P, ThreadStats), Error> {
+                let mut thread_results = P::default();
+                let mut stats = ThreadStats::default();
+                loop {
+                    // release the lock before processing so other workers can pick up blocks
+                    let block = receiver.lock().expect("Could not lock").recv();
+                    let Ok(block) = block else {
+                        break;
+                    };
+                    let block_started = Instant::now();
+                    match process_chunk(&block.data, block.offset, options) {
+                        Ok(chunk) => accumulate(&mut thread_results, chunk),
+                        Err(err) => {
+                            failed.store(true, Ordering::Relaxed);
+                            // keep draining so the reading thread never blocks on a full channel
+                            while receiver.lock().expect("Could not lock").recv().is_ok() {}
+                            return Err(err.shifted(block.offset, block.first_line).into());
+                        }
+                    }
+                    stats.busy += block_started.elapsed();
+                    stats.chunks += 1;
+                }
+                Ok((thread_results, stats))
+            }));
+        }
+
+        // split the stream into blocks, carrying a partial trailing line over to the next block
+        let read_result = (|| -> Result<(), Error> {
+            let mut carry: Vec<u8> = Vec::new();
+            let mut offset = 0;
+            let mut first_line = 0;
+            let mut header_lines = options.skip_header;
+            while !failed.load(Ordering::Relaxed) {
+                let mut data = std::mem::take(&mut carry);
+                let filled = data.len();
+                data.resize(filled + block_size, 0);
+                let read = read_full(&mut reader, &mut data[filled..])?;
+                data.truncate(filled + read);
+                // drop the header lines as they come in
+                while header_lines > 0 {
+                    let Some(newline) = data.iter().position(|byte| *byte == b'\n') else {
+                        break;
+                    };
+                    data.drain(..newline + 1);
+                    offset += newline as u64 + 1;
+                    first_line += 1;
+                    header_lines -= 1;
+                }
+                if read == 0 {
+                    // an unterminated last line may still be part of the header
+                    if !data.is_empty() && header_lines == 0 {
+                        let _ = sender.send(Block { data, offset, first_line });
+                    }
+                    break;
+                }
+                if let Some(newline) = data.iter().rposition(|byte| *byte == b'\n') {
+                    carry = data.split_off(newline + 1);
+                    let length = data.len() as u64;
+                    let lines = count_lines(&data);
+                    if sender.send(Block { data, offset, first_line }).is_err() {
+                        // all workers are gone, their errors are reported below
+                        break;
+                    }
+                    offset += length;
+                    first_line += lines;
+                } else {
+                    carry = data;
+                }
+            }
+            Ok(())
+        })();
+        drop(sender);
+
+        let mut partials = Vec::with_capacity(threads);
+        let mut error: Option<Error> = None;
+        for handle in handles {
+            // blocks before a failed one were handed out first and still get processed
+            match handle.join().expect("Worker thread panicked") {
+                Ok((partial, mut stats)) => {
+                    stats.idle = started.elapsed().saturating_sub(stats.busy);
+                    partials.push((partial, stats));
+                }
+                Err(err) => error = Some(match error.take() {
+                    Some(first) => first.earliest(err),
+                    None => err,
+                }),
+            }
+        }
+        match error {
+            Some(err) => Err(err),
+            None => read_result.map(|_| partials),
+        }
+    })
+}
+
+/// Fills `buf` as far as possible, returning less than `buf.len()` only at the end of the stream.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(filled)
+}
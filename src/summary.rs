@@ -0,0 +1,103 @@
+//! Totals over all stations, for a quick look at an input without the table of every station.
+
+use std::str;
+
+use crate::{ChunkResults, HyperLogLog, Report, SkippedLines, Station};
+
+/// How many readings, bytes and stations an input has, and the statistics of all readings together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    /// Number of readings.
+    pub rows: u64,
+    /// Size of the measurements read, as in [`Report::bytes`].
+    pub bytes: u64,
+    /// Number of distinct stations.
+    pub stations: u64,
+    /// Whether `stations` is estimated by a [`HyperLogLog`] rather than counted.
+    pub estimated: bool,
+    /// The station name with the most characters, the first in alphabetical order among equally long ones.
+    pub longest: Option<String>,
+    /// The station name with the fewest characters, the first in alphabetical order among equally short ones.
+    pub shortest: Option<String>,
+    /// All readings of all stations merged into one.
+    pub overall: Station,
+    /// Malformed lines that were skipped in lenient mode.
+    pub skipped: SkippedLines,
+}
+
+impl Summary {
+    /// Adds the readings of the station `name`, without counting it as distinct.
+    fn add_station(&mut self, name: &str, station: Station) {
+        self.rows += station.count();
+        self.overall.merge(station);
+        self.add_names(name, name);
+    }
+
+    /// Keeps `longest` or `shortest` if they beat the current ones.
+    fn add_names(&mut self, longest: &str, shortest: &str) {
+        let length = |name: &str| name.chars().count();
+        if self.longest.as_deref().is_none_or(|current| (length(longest), current) > (length(current), longest)) {
+            self.longest = Some(longest.to_owned());
+        }
+        if self.shortest.as_deref().is_none_or(|current| (length(shortest), shortest) < (length(current), current)) {
+            self.shortest = Some(shortest.to_owned());
+        }
+    }
+
+    /// Adds everything but the distinct stations of `other`.
+    fn merge(&mut self, other: Summary) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+        self.overall.merge(other.overall);
+        if let (Some(longest), Some(shortest)) = (&other.longest, &other.shortest) {
+            self.add_names(longest, shortest);
+        }
+        self.skipped.merge(other.skipped);
+    }
+}
+
+impl From<&Report> for Summary {
+    fn from(report: &Report) -> Self {
+        let mut summary = Summary { bytes: report.bytes, stations: report.stations.len() as u64, skipped: report.skipped, ..Summary::default() };
+        for (name, station) in &report.stations {
+            summary.add_station(name, station.clone());
+        }
+        summary
+    }
+}
+
+/// The summary of a worker that streams its input, counting the station names in a
+/// [`HyperLogLog`] instead of keeping them.
+#[derive(Default)]
+pub(crate) struct StreamSummary {
+    summary: Summary,
+    /// Created by the first chunk, since the default doesn't know the precision.
+    names: Option<HyperLogLog>,
+}
+
+impl StreamSummary {
+    /// Adds the stations of one chunk.
+    pub(crate) fn accumulate(&mut self, chunk: ChunkResults, precision: u8) {
+        let names = self.names.get_or_insert_with(|| HyperLogLog::new(precision));
+        for (name, station) in chunk.stations.into_entries() {
+            names.add(name);
+            self.summary.add_station(str::from_utf8(name).expect("Station names are validated while parsing"), station);
+        }
+        self.summary.bytes += chunk.bytes;
+        self.summary.skipped.merge(chunk.skipped);
+    }
+
+    pub(crate) fn merge(&mut self, other: StreamSummary) {
+        self.summary.merge(other.summary);
+        match (&mut self.names, other.names) {
+            (Some(names), Some(other)) => names.merge(&other),
+            (names, other) => *names = names.take().or(other),
+        }
+    }
+
+    /// Estimates the number of distinct stations.
+    pub(crate) fn finish(self) -> Summary {
+        let stations = self.names.map_or(0, |names| names.estimate().round() as u64);
+        Summary { stations, estimated: true, ..self.summary }
+    }
+}
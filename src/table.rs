@@ -0,0 +1,242 @@
+//! The per-chunk map from station names to their statistics.
+//!
+//! By default this is an open-addressing table with linear probing that is tuned for the short
+//! byte keys of station names. The `std-hashmap` feature swaps in the standard `HashMap` as a
+//! reference implementation, so results can be cross-checked.
+
+/// Multiplier of the name hash, from FxHash.
+const MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+
+/// Hashes a name eight bytes at a time, the last ones zero-padded and mixed with the length.
+///
+/// The line reader calls this once per line with the name it just scanned, so it stays in cache.
+#[inline]
+pub(crate) fn hash_name(name: &[u8]) -> u64 {
+    let mut words = name.chunks_exact(8);
+    let mut hash = name.len() as u64;
+    for word in &mut words {
+        hash = (hash.rotate_left(5) ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(MULTIPLIER);
+    }
+    let last = words.remainder().iter().rev().fold(0, |word, byte| word << 8 | *byte as u64);
+    hash = (hash.rotate_left(5) ^ last).wrapping_mul(MULTIPLIER);
+    // the table takes the low bits, which a product only mixes from below
+    hash = (hash ^ hash >> 33).wrapping_mul(MULTIPLIER);
+    hash ^ hash >> 33
+}
+
+#[cfg(not(feature = "std-hashmap"))]
+pub(crate) use open_addressing::StationTable;
+#[cfg(feature = "std-hashmap")]
+pub(crate) use reference::StationTable;
+
+#[cfg(not(feature = "std-hashmap"))]
+mod open_addressing {
+    /// Room for 512 names at a load factor of one half, more than the 413 stations of the 1BRC
+    /// sample data, without making small chunks pay for a large table. With up to 10,000
+    /// stations, the table doubles five times.
+    const INITIAL_CAPACITY: usize = 1024;
+
+    struct Slot<'a, S> {
+        hash: u64,
+        /// Zero for an empty slot, since names are never empty.
+        len: u32,
+        /// The first bytes of the name, zero-padded, to rule out most mismatches without following `name`.
+        prefix: u64,
+        name: &'a [u8],
+        value: S,
+    }
+
+    impl<S: Default> Slot<'_, S> {
+        /// Every empty slot holds a default value, so accumulators that keep more than running
+        /// statistics, like a histogram or a t-digest, box it and only allocate it for the first
+        /// reading to keep the table small.
+        fn empty() -> Self {
+            Self { hash: 0, len: 0, prefix: 0, name: &[], value: S::default() }
+        }
+    }
+
+    fn prefix(name: &[u8]) -> u64 {
+        let mut bytes = [0; 8];
+        let len = name.len().min(8);
+        bytes[..len].copy_from_slice(&name[..len]);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub(crate) struct StationTable<'a, S> {
+        slots: Vec<Slot<'a, S>>,
+        len: usize,
+    }
+
+    impl<S: Default> Default for StationTable<'_, S> {
+        fn default() -> Self {
+            Self { slots: (0..INITIAL_CAPACITY).map(|_| Slot::empty()).collect(), len: 0 }
+        }
+    }
+
+    impl<'a, S: Default> StationTable<'a, S> {
+        /// Creates a table that holds `len` names without growing.
+        pub(crate) fn with_capacity(len: usize) -> Self {
+            let capacity = (len * 2).next_power_of_two().max(16);
+            Self { slots: (0..capacity).map(|_| Slot::empty()).collect(), len: 0 }
+        }
+
+        /// Finds the slot for `name`, which is either its own or the empty one it would go into.
+        #[inline]
+        fn find(&self, name: &[u8], hash: u64) -> usize {
+            let mask = self.slots.len() - 1;
+            let len = name.len() as u32;
+            let prefix = prefix(name);
+            let mut index = hash as usize & mask;
+            loop {
+                let slot = &self.slots[index];
+                if slot.len == 0 || (slot.hash == hash && slot.len == len && slot.prefix == prefix && (len <= 8 || slot.name == name)) {
+                    return index;
+                }
+                index = (index + 1) & mask;
+            }
+        }
+
+        #[inline]
+        pub(crate) fn get_mut(&mut self, name: &[u8], hash: u64) -> Option<&mut S> {
+            let index = self.find(name, hash);
+            let slot = &mut self.slots[index];
+            match slot.len {
+                0 => None,
+                _ => Some(&mut slot.value),
+            }
+        }
+
+        pub(crate) fn get_or_default(&mut self, name: &'a [u8], hash: u64) -> &mut S {
+            // keep the load factor at or below one half
+            if (self.len + 1) * 2 > self.slots.len() {
+                self.grow();
+            }
+            let index = self.find(name, hash);
+            if self.slots[index].len == 0 {
+                self.slots[index] = Slot { hash, len: name.len() as u32, prefix: prefix(name), name, value: S::default() };
+                self.len += 1;
+            }
+            &mut self.slots[index].value
+        }
+
+        fn grow(&mut self) {
+            let capacity = self.slots.len() * 2;
+            let old = std::mem::replace(&mut self.slots, (0..capacity).map(|_| Slot::empty()).collect());
+            for slot in old.into_iter().filter(|slot| slot.len > 0) {
+                let index = self.find(slot.name, slot.hash);
+                self.slots[index] = slot;
+            }
+        }
+
+        /// Number of names in the table.
+        pub(crate) fn len(&self) -> usize {
+            self.len
+        }
+
+        pub(crate) fn into_entries(self) -> impl Iterator<Item = (&'a [u8], S)> {
+            self.slots.into_iter().filter(|slot| slot.len > 0).map(|slot| (slot.name, slot.value))
+        }
+    }
+}
+
+#[cfg(feature = "std-hashmap")]
+mod reference {
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    pub(crate) struct StationTable<'a, S> {
+        map: HashMap<&'a [u8], S>,
+    }
+
+    impl<'a, S: Default> StationTable<'a, S> {
+        pub(crate) fn with_capacity(len: usize) -> Self {
+            Self { map: HashMap::with_capacity(len) }
+        }
+
+        #[inline]
+        pub(crate) fn get_mut(&mut self, name: &[u8], _hash: u64) -> Option<&mut S> {
+            self.map.get_mut(name)
+        }
+
+        pub(crate) fn get_or_default(&mut self, name: &'a [u8], _hash: u64) -> &mut S {
+            self.map.entry(name).or_default()
+        }
+
+        pub(crate) fn len(&self) -> usize {
+            self.map.len()
+        }
+
+        pub(crate) fn into_entries(self) -> impl Iterator<Item = (&'a [u8], S)> {
+            self.map.into_iter()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use super::*;
+
+    /// Counts the names in `table`.
+    fn counts(table: StationTable<u32>) -> BTreeMap<Vec<u8>, u32> {
+        table.into_entries().map(|(name, count)| (name.to_vec(), count)).collect()
+    }
+
+    #[test]
+    fn colliding_hashes_are_probed() {
+        // also short names, which are compared by their prefix alone
+        let names: [&[u8]; 4] = [b"a", b"b", b"ab", b"a\0"];
+        let mut table = StationTable::default();
+        for (i, name) in names.iter().enumerate() {
+            *table.get_or_default(name, 7) += i as u32 + 1;
+        }
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(table.get_mut(name, 7).copied(), Some(i as u32 + 1));
+        }
+        assert_eq!(table.get_mut(b"c", 7), None);
+        assert_eq!(counts(table).len(), names.len());
+    }
+
+    #[test]
+    fn long_names_with_the_same_prefix_are_told_apart() {
+        let names: [&[u8]; 3] = [b"Station-Long-Name-1", b"Station-Long-Name-2", b"Station-Long-Name-10"];
+        let mut table = StationTable::default();
+        for name in names {
+            *table.get_or_default(name, 0) += 1;
+        }
+        *table.get_or_default(names[1], 0) += 1;
+        assert_eq!(table.get_mut(b"Station-Long-Name-3", 0), None);
+        assert_eq!(counts(table), BTreeMap::from([(names[0].to_vec(), 1), (names[1].to_vec(), 2), (names[2].to_vec(), 1)]));
+    }
+
+    #[test]
+    fn growing_keeps_every_name() {
+        let names: Vec<Vec<u8>> = (0..10_000).map(|i| format!("Station {}", i).into_bytes()).collect();
+        for mut table in [StationTable::default(), StationTable::with_capacity(names.len())] {
+            for (i, name) in names.iter().enumerate() {
+                *table.get_or_default(name, hash_name(name)) = i as u32;
+            }
+            for (i, name) in names.iter().enumerate() {
+                assert_eq!(table.get_mut(name, hash_name(name)).copied(), Some(i as u32));
+            }
+            assert_eq!(counts(table).len(), names.len());
+        }
+    }
+
+    #[test]
+    fn counts_agree_with_the_standard_hashmap() {
+        let lines: Vec<Vec<u8>> = (0..50_000_usize).map(|i| format!("{}-{}", ["Hamburg", "St. John's", "Zürich", ""][i % 4], (i * 7919) % 3001).into_bytes()).collect();
+        let mut table = StationTable::default();
+        let mut expected: HashMap<&[u8], u32> = HashMap::new();
+        for name in &lines {
+            match table.get_mut(name, hash_name(name)) {
+                Some(count) => *count += 1,
+                None => *table.get_or_default(name, hash_name(name)) = 1,
+            }
+            *expected.entry(name).or_default() += 1;
+        }
+        let expected: BTreeMap<Vec<u8>, u32> = expected.into_iter().map(|(name, count)| (name.to_vec(), count)).collect();
+        assert_eq!(counts(table), expected);
+    }
+}
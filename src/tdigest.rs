@@ -0,0 +1,176 @@
+//! Approximate percentiles of readings with any precision.
+//!
+//! A t-digest summarizes the readings as weighted centroids, which are kept small near the
+//! extremes and large around the median, so tail percentiles stay accurate with few centroids.
+//! New readings are buffered and merged into the centroids in batches, the merging variant from
+//! Dunning and Ertl, "Computing Extremely Accurate Quantiles Using t-Digests".
+
+use std::{borrow::Cow, f64::consts::PI};
+
+use crate::Station;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// A mergeable sketch of the readings of a station, for approximate percentiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TDigest {
+    compression: f64,
+    /// Sorted by mean.
+    centroids: Vec<Centroid>,
+    /// Readings and centroids of other digests that aren't merged into `centroids` yet.
+    unmerged: Vec<Centroid>,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    /// Creates an empty digest. The compression bounds the number of centroids to about twice
+    /// its value, and the error of a percentile shrinks roughly in proportion to it; 100 is a
+    /// common choice.
+    pub fn new(compression: f64) -> Self {
+        assert!(compression.is_finite() && compression >= 1.0, "the compression of a t-digest must be a finite number of at least 1");
+        Self { compression, centroids: Vec::new(), unmerged: Vec::new(), count: 0, min: f64::MAX, max: f64::MIN }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.unmerged.push(Centroid { mean: value, weight: 1.0 });
+        if self.unmerged.len() >= self.buffer_limit() {
+            self.compress();
+        }
+    }
+
+    pub fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.unmerged.extend_from_slice(&other.centroids);
+        self.unmerged.extend_from_slice(&other.unmerged);
+        if self.unmerged.len() >= self.buffer_limit() {
+            self.compress();
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of centroids the readings are summarized in.
+    pub fn centroids(&self) -> usize {
+        self.compressed().centroids.len()
+    }
+
+    /// Estimates the reading that `percentile` percent of all readings are less than or equal to.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        self.compressed().quantile(percentile / 100.0)
+    }
+
+    fn buffer_limit(&self) -> usize {
+        (self.compression as usize).saturating_mul(5).max(32)
+    }
+
+    /// Borrows the digest if it has no unmerged readings, or merges them into a copy.
+    fn compressed(&self) -> Cow<'_, TDigest> {
+        if self.unmerged.is_empty() {
+            return Cow::Borrowed(self);
+        }
+        let mut digest = self.clone();
+        digest.compress();
+        Cow::Owned(digest)
+    }
+
+    /// Merges the buffered readings into the centroids, keeping each centroid within one unit of
+    /// the scale function `k(q) = compression / 2π * asin(2q - 1)`.
+    fn compress(&mut self) {
+        if self.unmerged.is_empty() {
+            return;
+        }
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.unmerged);
+        all.sort_unstable_by(|a, b| a.mean.total_cmp(&b.mean));
+
+        let total = self.count as f64;
+        let mut merged = Vec::with_capacity((self.compression as usize).saturating_mul(2).min(all.len()));
+        let mut current = all[0];
+        let mut weight_before = 0.0;
+        let mut limit = total * self.next_quantile(0.0);
+        for next in &all[1..] {
+            if weight_before + current.weight + next.weight <= limit {
+                current.weight += next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / current.weight;
+            } else {
+                weight_before += current.weight;
+                merged.push(current);
+                limit = total * self.next_quantile(weight_before / total);
+                current = *next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// The quantile one unit of the scale function above `quantile`.
+    fn next_quantile(&self, quantile: f64) -> f64 {
+        let angle = (2.0 * quantile - 1.0).clamp(-1.0, 1.0).asin() + 2.0 * PI / self.compression;
+        if angle >= PI / 2.0 {
+            return 1.0;
+        }
+        (angle.sin() + 1.0) / 2.0
+    }
+
+    /// Takes the mean of the centroid whose weight covers the index, since a centroid of one
+    /// frequent reading must return that reading, and only interpolates towards the minimum and
+    /// maximum in the outer halves of the first and last centroids.
+    fn quantile(&self, quantile: f64) -> Option<f64> {
+        let centroids = &self.centroids;
+        let (first, last) = (centroids.first()?, centroids.last()?);
+        let total = self.count as f64;
+        let index = quantile.clamp(0.0, 1.0) * total;
+        if centroids.len() == 1 {
+            return Some(self.min + (self.max - self.min) * quantile.clamp(0.0, 1.0));
+        }
+        if index < 1.0 {
+            return Some(self.min);
+        }
+        if first.weight > 1.0 && index < first.weight / 2.0 {
+            return Some(self.min + (index - 1.0) / (first.weight / 2.0 - 1.0) * (first.mean - self.min));
+        }
+        if index > total - 1.0 {
+            return Some(self.max);
+        }
+        if last.weight > 1.0 && total - index <= last.weight / 2.0 {
+            return Some(self.max - (total - index - 1.0) / (last.weight / 2.0 - 1.0) * (self.max - last.mean));
+        }
+
+        let mut weight_before = 0.0;
+        for centroid in centroids {
+            if index <= weight_before + centroid.weight {
+                return Some(centroid.mean);
+            }
+            weight_before += centroid.weight;
+        }
+        Some(self.max)
+    }
+}
+
+/// A [`Station`] that also summarizes its readings in a t-digest.
+#[derive(Default)]
+pub(crate) struct SketchStation {
+    pub(crate) station: Station,
+    pub(crate) sketch: Option<Box<TDigest>>,
+}
+
+impl SketchStation {
+    #[inline]
+    pub(crate) fn update(&mut self, value: f64, compression: f64) {
+        self.station.update(value);
+        self.sketch.get_or_insert_with(|| Box::new(TDigest::new(compression))).add(value);
+    }
+}
@@ -0,0 +1,41 @@
+use std::{fs, process::{Command, Output}};
+
+use common::input_file;
+
+mod common;
+
+/// The first lines of the `weather_stations.csv` that 1BRC generates its measurements from.
+const WEATHER_STATIONS: &str = "# Adapted from https://simplemaps.com/data/world-cities\n\
+    # Licensed under Creative Commons Attribution 4.0 (https://creativecommons.org/licenses/by/4.0/)\n\
+    Tokyo;35.6897\n\
+    Jakarta;-6.1750\n\
+    Delhi;28.6100\n\
+    Tokyo;5\n";
+
+fn onebrc(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_onebrc")).args(args).output().expect("Couldn't run onebrc")
+}
+
+#[test]
+fn readings_with_any_number_of_decimals() {
+    let path = input_file("cli-weather-stations", WEATHER_STATIONS.as_bytes());
+    let path = path.to_str().unwrap();
+
+    let output = onebrc(&["--comment-prefix", "#", path]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "{Delhi=28.6/28.6/28.6, Jakarta=-6.2/-6.2/-6.2, Tokyo=5.0/20.3/35.7}\n");
+
+    // fixed point only takes exactly one decimal
+    let output = onebrc(&["--comment-prefix", "#", "--fixed-point", path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("{}: line 3 (byte offset 153): invalid temperature value\n", path));
+
+    let output = onebrc(&["--comment-prefix", "#", "--sketch", "100", "--percentiles", "50", "--format", "json", path]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("\"Tokyo\":{\"min\":5.0,\"mean\":20.3,\"max\":35.7,\"count\":2,"));
+
+    // histograms count tenths
+    let output = onebrc(&["--percentiles", "50", path]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_file(path).unwrap();
+}
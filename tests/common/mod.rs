@@ -0,0 +1,58 @@
+// every test binary compiles this module, but each uses only some of the helpers
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use onebrc::{aggregate_files_report, aggregate_reader_report, aggregate_report, write_results, Chunking, Error, Options, OutputOptions, Report, Station};
+
+/// Writes `contents` to a file that only this test uses.
+pub fn input_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("onebrc-{}-{}.txt", name, std::process::id()));
+    fs::write(&path, contents).expect("Unable to write test input");
+    path
+}
+
+/// The results as the binary prints them, so that results with float sums merged in a different
+/// order compare equal.
+pub fn text(results: &BTreeMap<String, Station>) -> String {
+    let mut out = Vec::new();
+    write_results(&mut out, results, &OutputOptions::default()).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// `options` with 1, 3 and 8 threads, each split into a queue of chunks and per thread, read
+/// without memory-mapping and through a window of `window_size` bytes.
+pub fn option_matrix(options: &Options, window_size: usize) -> Vec<Options> {
+    let mut matrix = Vec::new();
+    for threads in [1, 3, 8] {
+        let options = Options { threads, chunking: Chunking::Queue, ..options.clone() };
+        matrix.push(options.clone());
+        matrix.push(Options { chunking: Chunking::PerThread, ..options.clone() });
+        matrix.push(Options { mmap: false, ..options.clone() });
+        matrix.push(Options { window_size: Some(window_size), ..options });
+    }
+    matrix
+}
+
+/// Aggregates `contents` as a file, as it is and without memory-mapping, and as a stream.
+pub fn aggregate_all(name: &str, contents: &[u8], options: &Options) -> Vec<Result<Report, Error>> {
+    let path = input_file(name, contents);
+    let results = vec![
+        aggregate_report(&path, options),
+        aggregate_report(&path, &Options { mmap: false, ..options.clone() }),
+        aggregate_reader_report(contents, options),
+    ];
+    fs::remove_file(path).unwrap();
+    results
+}
+
+/// Like [`aggregate_all`], but also reads the file in windows of `window_size` bytes and as the
+/// only one in a list of files.
+pub fn aggregate_every_path(name: &str, contents: &[u8], options: &Options, window_size: usize) -> Vec<Result<Report, Error>> {
+    let mut results = aggregate_all(name, contents, options);
+    let path = input_file(name, contents);
+    results.push(aggregate_report(&path, &Options { window_size: Some(window_size), ..options.clone() }));
+    results.push(aggregate_files_report(&[&path], options));
+    fs::remove_file(path).unwrap();
+    results
+}
@@ -0,0 +1,72 @@
+use std::io::Write;
+
+use common::{aggregate_all, text};
+use flate2::{write::GzEncoder, Compression};
+use onebrc::{aggregate_slice, Error, ErrorMode, Options, ParseError, ParseErrorKind};
+
+mod common;
+
+fn contents(lines: usize) -> Vec<u8> {
+    let mut contents = Vec::new();
+    for i in 0..lines {
+        contents.extend_from_slice(format!("Station{};{}.{}\n", i % 11, (i % 90) as i64 - 30, i % 10).as_bytes());
+    }
+    contents
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Compresses every `frame_size` bytes as an independent zstd frame, so most lines span two frames.
+fn zstd_frames(data: &[u8], frame_size: usize) -> Vec<u8> {
+    data.chunks(frame_size).flat_map(|frame| zstd::encode_all(frame, 1).unwrap()).collect()
+}
+
+#[test]
+fn compressed_input_gives_the_same_results() {
+    let contents = contents(1000);
+    // exact sums, so that no mean depends on the order the chunks are merged in
+    let options = Options { fixed_point: true, block_size: 256, ..Options::default() };
+    let expected = text(&aggregate_slice(&contents, &options).unwrap());
+    let inputs = [("gzip", gzip(&contents)), ("zstd", zstd_frames(&contents, usize::MAX)), ("frames-13", zstd_frames(&contents, 13)), ("frames-1000", zstd_frames(&contents, 1000))];
+    for (name, compressed) in inputs {
+        for threads in [1, 3, 8] {
+            for result in aggregate_all(name, &compressed, &Options { threads, ..options.clone() }) {
+                assert_eq!(text(&result.unwrap().stations), expected, "{} with {} threads", name, threads);
+            }
+        }
+    }
+}
+
+#[test]
+fn errors_are_positioned_in_the_decompressed_input() {
+    // malformed lines in the middle and at the unterminated end
+    let mut contents = contents(300);
+    let middle = contents.len();
+    contents.extend_from_slice(b"Hamburg;1.x\n");
+    contents.extend_from_slice(&self::contents(300));
+    contents.extend_from_slice(b";1.0");
+    let first = ParseError { kind: ParseErrorKind::InvalidNumber, offset: middle as u64, line: 301 };
+
+    for frame_size in [2, 7, 100, 4096] {
+        let compressed = zstd_frames(&contents, frame_size);
+        for threads in [1, 4] {
+            for result in aggregate_all("errors", &compressed, &Options { threads, ..Options::default() }) {
+                match result {
+                    Err(Error::Parse(err)) => assert_eq!(err, first, "frames of {} bytes", frame_size),
+                    other => panic!("expected a parse error, got {:?}", other),
+                }
+            }
+            let lenient = Options { threads, fixed_point: true, errors: ErrorMode::Lenient, ..Options::default() };
+            let expected = text(&aggregate_slice(&contents, &lenient).unwrap());
+            for result in aggregate_all("lenient", &compressed, &lenient) {
+                let report = result.unwrap();
+                assert_eq!((report.skipped.invalid_number, report.skipped.empty_name), (1, 1));
+                assert_eq!(text(&report.stations), expected);
+            }
+        }
+    }
+}
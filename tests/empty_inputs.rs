@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+use common::{aggregate_all, text};
+use onebrc::{aggregate_slice, Options, Station};
+
+mod common;
+
+/// Runs every input path on `contents` and checks that they agree with each other.
+fn aggregate_agreeing(name: &str, contents: &[u8], threads: usize) -> BTreeMap<String, Station> {
+    let options = Options { threads, block_size: 4, ..Options::default() };
+    let expected = aggregate_slice(contents, &options).unwrap();
+    for options in [Options { window_size: Some(4), ..options.clone() }, options] {
+        for result in aggregate_all(name, contents, &options) {
+            // the float statistics depend on the merge order, so compare the printed values
+            assert_eq!(text(&result.unwrap().stations), text(&expected));
+        }
+    }
+    expected
+}
+
+#[test]
+fn empty_file_prints_empty_braces() {
+    let results = aggregate_agreeing("empty", b"", 4);
+    assert!(results.is_empty());
+    assert_eq!(text(&results), "{}\n");
+}
+
+#[test]
+fn blank_lines_only() {
+    let results = aggregate_agreeing("blank", b"\n\n\n", 4);
+    assert!(results.is_empty());
+    assert_eq!(text(&results), "{}\n");
+}
+
+#[test]
+fn single_line_with_and_without_newline() {
+    for contents in [&b"Hamburg;12.0"[..], b"Hamburg;12.0\n"] {
+        let results = aggregate_agreeing("single", contents, 4);
+        assert_eq!(text(&results), "{Hamburg=12.0/12.0/12.0}\n");
+    }
+}
+
+#[test]
+fn fewer_lines_than_threads() {
+    let results = aggregate_agreeing("few", b"Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n", 16);
+    assert_eq!(text(&results), "{Bulawayo=8.9/8.9/8.9, Hamburg=-3.4/4.3/12.0}\n");
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use common::{aggregate_every_path, input_file};
+use onebrc::{aggregate_files_report, aggregate_report, aggregate_slice, write_results, Chunking, Format, Options, OutputOptions, Reading, Station};
+
+mod common;
+
+/// A value and the offset of its line.
+type Position = (f64, u64);
+
+/// Measurements after a header line, with every reading's value and line offset per station.
+fn measurements(lines: usize, seed: usize) -> (Vec<u8>, BTreeMap<String, Vec<Position>>) {
+    let mut contents = b"station;temperature\n".to_vec();
+    let mut readings: BTreeMap<String, Vec<Position>> = BTreeMap::new();
+    for i in 0..lines {
+        // many repeated values, so ties are broken by position
+        let tenths = ((i * 7919 + seed) % 1999) as i64 / 3 * 3 - 999;
+        let name = format!("Station{}", i % 6);
+        readings.entry(name.clone()).or_default().push((tenths as f64 / 10.0, contents.len() as u64));
+        contents.extend_from_slice(format!("{};{}{}.{}\n", name, if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10).as_bytes());
+    }
+    (contents, readings)
+}
+
+/// The `limit` highest and lowest readings, by sorting.
+fn expected(readings: &[Position], limit: usize) -> (Vec<Position>, Vec<Position>) {
+    let mut highest = readings.to_vec();
+    highest.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut lowest = readings.to_vec();
+    lowest.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    highest.truncate(limit);
+    lowest.truncate(limit);
+    (highest, lowest)
+}
+
+fn positions(readings: &[Reading]) -> Vec<Position> {
+    readings.iter().map(|reading| (reading.value, reading.offset)).collect()
+}
+
+fn check(stations: &BTreeMap<String, Station>, readings: &BTreeMap<String, Vec<Position>>, limit: usize, what: &str) {
+    for (name, readings) in readings {
+        let extremes = stations[name].extremes().unwrap();
+        let (highest, lowest) = expected(readings, limit);
+        assert_eq!(positions(extremes.highest()), highest, "highest of {} {}", name, what);
+        assert_eq!(positions(extremes.lowest()), lowest, "lowest of {} {}", name, what);
+    }
+}
+
+#[test]
+fn extremes_match_sorting_on_every_input_path() {
+    let (contents, readings) = measurements(3000, 0);
+    let frames: Vec<u8> = contents.chunks(1000).flat_map(|frame| zstd::encode_all(frame, 1).unwrap()).collect();
+    let compressed = input_file("extremes-zstd", &frames);
+    for limit in [1, 4] {
+        for threads in [1, 3, 8] {
+            let options = Options { threads, extremes: limit, skip_header: 1, chunking: Chunking::Queue, chunk_size: 700, block_size: 700, ..Options::default() };
+            for options in [options.clone(), Options { fixed_point: true, ..options.clone() }, Options { fixed_point: true, histograms: true, ..options.clone() }, Options { sketch: Some(50.0), ..options.clone() }] {
+                let mut results = aggregate_every_path("extremes", &contents, &options, 100);
+                results.push(aggregate_report(&compressed, &options));
+                for result in results {
+                    check(&result.unwrap().stations, &readings, limit, &format!("with {:?}", options));
+                }
+            }
+        }
+    }
+    fs::remove_file(compressed).unwrap();
+}
+
+#[test]
+fn extremes_name_their_file() {
+    let inputs = [measurements(500, 0), measurements(700, 3)];
+    let paths: Vec<PathBuf> = inputs.iter().enumerate().map(|(i, (contents, _))| input_file(&format!("extremes-file{}", i), contents)).collect();
+    for threads in [1, 4] {
+        let options = Options { threads, extremes: 3, skip_header: 1, per_file: true, chunking: Chunking::Queue, chunk_size: 400, ..Options::default() };
+        let report = aggregate_files_report(&paths, &options).unwrap();
+        for ((file, path), (_, readings)) in report.files.iter().zip(&paths).zip(&inputs) {
+            check(&file.stations, readings, 3, &format!("in {:?}", path));
+        }
+
+        // across files, equal readings are ordered by file and then by offset
+        for (name, station) in &report.stations {
+            let extremes = station.extremes().unwrap();
+            let mut all = Vec::new();
+            for (path, (_, readings)) in paths.iter().zip(&inputs) {
+                all.extend(readings[name].iter().map(|(value, offset)| (*value, path.clone(), *offset)));
+            }
+            let position = |a: &(f64, PathBuf, u64), b: &(f64, PathBuf, u64)| (&a.1, a.2).cmp(&(&b.1, b.2));
+            let mut highest = all.clone();
+            highest.sort_by(|a, b| b.0.total_cmp(&a.0).then(position(a, b)));
+            let mut lowest = all;
+            lowest.sort_by(|a, b| a.0.total_cmp(&b.0).then(position(a, b)));
+            let found = |readings: &[Reading]| readings.iter().map(|reading| (reading.value, reading.file.as_deref().unwrap().to_owned(), reading.offset)).collect::<Vec<_>>();
+            assert_eq!(found(extremes.highest()), highest[..3], "highest of {} with {} threads", name, threads);
+            assert_eq!(found(extremes.lowest()), lowest[..3], "lowest of {} with {} threads", name, threads);
+        }
+    }
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn extremes_are_written_in_json() {
+    let options = Options { extremes: 2, ..Options::default() };
+    let results = aggregate_slice(b"a;1.0\na;-0.0\na;3.5\nb;-2.5\n", &options).unwrap();
+    let mut out = Vec::new();
+    write_results(&mut out, &results, &OutputOptions { format: Format::Json, ..OutputOptions::default() }).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"a\":{\"min\":0.0,\"mean\":1.5,\"max\":3.5,\"count\":3,\"sum\":4.5,\
+         \"highest\":[{\"value\":3.5,\"offset\":13},{\"value\":1.0,\"offset\":0}],\"lowest\":[{\"value\":0.0,\"offset\":6},{\"value\":1.0,\"offset\":0}]},\
+         \"b\":{\"min\":-2.5,\"mean\":-2.5,\"max\":-2.5,\"count\":1,\"sum\":-2.5,\
+         \"highest\":[{\"value\":-2.5,\"offset\":19}],\"lowest\":[{\"value\":-2.5,\"offset\":19}]}}\n"
+    );
+}
@@ -0,0 +1,75 @@
+use std::fs;
+
+use common::{aggregate_all, input_file, option_matrix, text};
+use onebrc::{aggregate_files_report, aggregate_slice, Error, Options, ParseErrorKind, Report};
+
+mod common;
+
+/// Measurements with a long comment line, longer than the chunks, after every tenth line.
+fn measurements() -> Vec<u8> {
+    let mut contents = String::new();
+    for i in 0..600 {
+        contents += &format!("Station{};{}.{}\n", i % 9, (i % 80) as i64 - 25, i % 10);
+        if i % 10 == 3 {
+            contents += &format!("# {}\n", "comment;1.0 ".repeat(i % 40));
+        }
+    }
+    contents.into_bytes()
+}
+
+/// The header of weather_stations.csv, which has no delimiter in its second line.
+const HEADER: &[u8] = b"# Adapted from https://simplemaps.com/data/world-cities\n# Licensed under Creative Commons Attribution 4.0\n";
+
+fn options() -> Vec<Options> {
+    option_matrix(&Options { chunk_size: 97, block_size: 97, ..Options::default() }, 41)
+}
+
+/// Aggregates two copies of the same file with `contents`.
+fn aggregate_twice(name: &str, contents: &[u8], options: &Options) -> Result<Report, Error> {
+    let path = input_file(&format!("{}-twice", name), contents);
+    let result = aggregate_files_report(&[&path, &path], options);
+    fs::remove_file(path).unwrap();
+    result
+}
+
+#[test]
+fn header_and_comment_lines_are_skipped() {
+    let clean: Vec<u8> = measurements().split_inclusive(|byte| *byte == b'\n').filter(|line| !line.starts_with(b"#")).flatten().copied().collect();
+    let expected = text(&aggregate_slice(&clean, &Options::default()).unwrap());
+    let twice = text(&aggregate_slice(&[clean.as_slice(), &clean].concat(), &Options::default()).unwrap());
+
+    let mut contents = b"station;temperature\n".to_vec();
+    contents.extend_from_slice(HEADER);
+    contents.extend_from_slice(&measurements());
+    for options in options() {
+        let options = Options { skip_header: 1, comment_prefix: Some("#".to_owned()), ..options };
+        for result in aggregate_all("comments", &contents, &options) {
+            assert_eq!(text(&result.unwrap().stations), expected, "{:?}", options);
+        }
+        assert_eq!(text(&aggregate_twice("comments", &contents, &options).unwrap().stations), twice, "{:?}", options);
+    }
+}
+
+#[test]
+fn header_lines_are_counted_in_error_positions() {
+    let contents = b"station;temperature\nunit;celsius\na;1.0\nb;x\n";
+    for options in options() {
+        let options = Options { skip_header: 2, ..options };
+        for result in aggregate_all("header-errors", contents, &options).into_iter().chain([aggregate_twice("header-errors", contents, &options)]) {
+            let err = match result {
+                Err(Error::InFile(_, err)) => *err,
+                Err(err) => err,
+                other => panic!("expected an error, got {:?}", other),
+            };
+            match err {
+                Error::Parse(err) => assert_eq!((err.kind, err.offset, err.line), (ParseErrorKind::InvalidNumber, 39, 4)),
+                other => panic!("expected a parse error, got {:?}", other),
+            }
+        }
+    }
+    // more header lines than the input has
+    let options = Options { skip_header: 10, ..Options::default() };
+    for result in aggregate_all("header-errors", contents, &options).into_iter().chain([aggregate_twice("header-errors", contents, &options)]) {
+        assert!(result.unwrap().stations.is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+use common::{aggregate_all, option_matrix, text};
+use onebrc::{aggregate_slice, InputFormat, LineEnding, Options};
+
+mod common;
+
+/// The same measurements, written with `delimiter`, `decimal_separator` and `line_ending`.
+fn contents(delimiter: &str, decimal_separator: &str, line_ending: &str) -> Vec<u8> {
+    let mut contents = String::new();
+    for i in 0..1500 {
+        let name = ["Hamburg", "St. Paul, MN", "Tab\tCity", "Semi;colon"][i % 4];
+        contents += &format!("{}{}{}{}{}{}", name, delimiter, (i % 120) as i64 - 60, decimal_separator, i % 10, line_ending);
+    }
+    contents.into_bytes()
+}
+
+#[test]
+fn every_input_path_honours_the_input_format() {
+    let expected = text(&aggregate_slice(&contents(";", ".", "\n"), &Options::default()).unwrap());
+    let formats = [
+        (",", ",", "\r\n", InputFormat { delimiter: b',', decimal_separator: b',', line_ending: LineEnding::CrLf }),
+        ("\t", ".", "\r\n", InputFormat { delimiter: b'\t', decimal_separator: b'.', line_ending: LineEnding::CrLf }),
+        (";", ",", "\n", InputFormat { decimal_separator: b',', ..InputFormat::default() }),
+    ];
+    for (delimiter, decimal_separator, line_ending, input_format) in formats {
+        let contents = contents(delimiter, decimal_separator, line_ending);
+        for fixed_point in [true, false] {
+            for options in option_matrix(&Options { input_format, fixed_point, chunk_size: 333, block_size: 333, ..Options::default() }, 77) {
+                for result in aggregate_all("format", &contents, &options) {
+                    assert_eq!(text(&result.unwrap().stations), expected, "{:?}", options);
+                }
+            }
+        }
+    }
+}
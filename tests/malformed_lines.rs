@@ -0,0 +1,51 @@
+use common::{aggregate_all, option_matrix, text};
+use onebrc::{Error, ErrorMode, Options, ParseError, ParseErrorKind, Report, SkippedLines};
+
+mod common;
+
+/// 1000 valid lines with a few malformed ones in between.
+fn contents() -> Vec<u8> {
+    let mut contents = Vec::new();
+    for i in 0..1000 {
+        match i {
+            400 => contents.extend_from_slice(b"no delimiter\n"),
+            500 => contents.extend_from_slice(b"Hamburg;12.x\n"),
+            600 => contents.extend_from_slice(b";1.0\n"),
+            700 => contents.extend_from_slice(b"\xffburg;1.0\n"),
+            _ => contents.extend_from_slice(format!("Station{};{}.{}\n", i % 7, i % 50, i % 10).as_bytes()),
+        }
+    }
+    contents
+}
+
+/// Runs the file and the stream paths on the same input with several chunkings.
+fn run_all(name: &str, contents: &[u8], errors: ErrorMode) -> Vec<Result<Report, Error>> {
+    let options = Options { errors, fixed_point: true, chunk_size: 300, block_size: 100, ..Options::default() };
+    option_matrix(&options, 64).iter().flat_map(|options| aggregate_all(name, contents, options)).collect()
+}
+
+#[test]
+fn strict_mode_reports_position_of_first_error() {
+    let contents = contents();
+    let offset = contents.split(|byte| *byte == b'\n').take(400).map(|line| line.len() as u64 + 1).sum();
+    for result in run_all("strict", &contents, ErrorMode::Strict) {
+        match result {
+            Err(Error::Parse(err)) => assert_eq!(err, ParseError { kind: ParseErrorKind::MissingDelimiter, offset, line: 401 }),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn lenient_mode_counts_skipped_lines() {
+    let expected = SkippedLines { missing_delimiter: 1, invalid_number: 1, empty_name: 1, invalid_utf8: 1 };
+    let results = run_all("lenient", &contents(), ErrorMode::Lenient);
+    let first = text(&results[0].as_ref().unwrap().stations);
+    for result in &results {
+        let report = result.as_ref().unwrap();
+        assert_eq!(report.skipped, expected);
+        assert_eq!(report.stations.values().map(|station| station.count()).sum::<u64>(), 996);
+        // the float sums depend on the merge order, so compare the printed values
+        assert_eq!(text(&report.stations), first);
+    }
+}
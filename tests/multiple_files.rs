@@ -0,0 +1,96 @@
+use std::fs;
+
+use common::{input_file, option_matrix, text};
+use onebrc::{aggregate_files_report, aggregate_slice, Error, Options, ParseErrorKind};
+
+mod common;
+
+/// Three shards of 300 lines, the second one without a trailing newline, and an empty one.
+fn shards() -> Vec<Vec<u8>> {
+    let mut shards = vec![Vec::new(); 4];
+    for i in 0..900 {
+        let line = format!("Station{};{}.{}\n", i % 13, (i % 70) as i64 - 20, i % 10);
+        shards[i / 300].extend_from_slice(line.as_bytes());
+    }
+    shards[1].pop();
+    shards
+}
+
+fn options() -> Vec<Options> {
+    option_matrix(&Options { per_file: true, chunk_size: 500, ..Options::default() }, 64)
+}
+
+#[test]
+fn files_are_combined_and_broken_down() {
+    let shards = shards();
+    let paths: Vec<_> = shards.iter().enumerate().map(|(i, shard)| input_file(&format!("shard{}", i), shard)).collect();
+
+    let mut concatenated = Vec::new();
+    for shard in &shards {
+        concatenated.extend_from_slice(shard);
+        if !concatenated.ends_with(b"\n") {
+            concatenated.push(b'\n');
+        }
+    }
+    let expected = text(&aggregate_slice(&concatenated, &Options::default()).unwrap());
+
+    for options in options() {
+        let report = aggregate_files_report(&paths, &options).unwrap();
+        assert_eq!(text(&report.stations), expected, "{:?}", options);
+        assert_eq!(report.files.len(), shards.len());
+        for ((file, path), shard) in report.files.iter().zip(&paths).zip(&shards) {
+            assert_eq!(&file.path, path);
+            assert_eq!(text(&file.stations), text(&aggregate_slice(shard, &Options::default()).unwrap()));
+        }
+        let without_breakdown = aggregate_files_report(&paths, &Options { per_file: false, ..options }).unwrap();
+        assert_eq!(text(&without_breakdown.stations), expected);
+        assert!(without_breakdown.files.is_empty());
+    }
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn errors_name_the_file() {
+    let good = input_file("good", b"a;1.0\nb;2.0\n");
+    let bad = input_file("bad", b"a;1.0\nb;2.0\nc;x\n");
+    for options in options() {
+        match aggregate_files_report(&[&good, &bad], &options) {
+            Err(Error::InFile(path, err)) => {
+                assert_eq!(path, bad);
+                match *err {
+                    Error::Parse(err) => assert_eq!((err.kind, err.offset, err.line), (ParseErrorKind::InvalidNumber, 12, 3)),
+                    other => panic!("expected a parse error, got {:?}", other),
+                }
+            }
+            other => panic!("expected an error in {:?}, got {:?}", bad, other),
+        }
+    }
+    fs::remove_file(good).unwrap();
+    fs::remove_file(bad).unwrap();
+}
+
+#[test]
+fn the_first_bad_file_is_reported() {
+    // one malformed line at the end of the first bad file, and nothing but malformed lines in the
+    // next one, so that other threads fail on its chunks while the first error is still ahead
+    let late = [shards().concat().repeat(150).as_slice(), b"late;x\n"].concat();
+    let paths = [input_file("first-good", &shards()[0]), input_file("first-late", &late), input_file("first-early", "early;x\n".repeat(100_000).as_bytes())];
+    let line = late.iter().filter(|byte| **byte == b'\n').count() as u64;
+    for options in option_matrix(&Options { chunk_size: 16 * 1024, ..Options::default() }, 4096) {
+        match aggregate_files_report(&paths, &options) {
+            Err(Error::InFile(path, err)) => {
+                assert_eq!(path, paths[1], "{:?}", options);
+                match *err {
+                    Error::Parse(err) => assert_eq!((err.kind, err.line), (ParseErrorKind::InvalidNumber, line)),
+                    other => panic!("expected a parse error, got {:?}", other),
+                }
+            }
+            other => panic!("expected an error in {:?}, got {:?}", paths[1], other),
+        }
+    }
+    for path in paths {
+        fs::remove_file(path).unwrap();
+    }
+}
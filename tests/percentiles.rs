@@ -0,0 +1,108 @@
+use std::{collections::BTreeMap, fs};
+
+use common::{aggregate_every_path, input_file};
+use onebrc::{aggregate_files_report, aggregate_slice, write_results, Chunking, Format, Histogram, Options, OutputOptions, Station};
+
+mod common;
+
+/// Readings of a few stations, skewed so that every station has a single most frequent value.
+fn measurements(lines: usize) -> (Vec<u8>, BTreeMap<String, Vec<i16>>) {
+    let mut contents = String::new();
+    let mut readings: BTreeMap<String, Vec<i16>> = BTreeMap::new();
+    for i in 0..lines {
+        let station = i % 5;
+        let tenths = match i % 4 {
+            0 => 100 * station as i16 - 250,
+            _ => ((i * 7919) % 1999) as i16 - 999,
+        };
+        let name = format!("Station{}", station);
+        contents += &format!("{};{}{}.{}\n", name, if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10);
+        readings.entry(name).or_default().push(tenths);
+    }
+    (contents.into_bytes(), readings)
+}
+
+/// The nearest-rank percentile of sorted readings.
+fn nearest_rank(sorted: &[i16], percentile: f64) -> f64 {
+    let rank = ((percentile / 100.0 * sorted.len() as f64).ceil() as usize).max(1);
+    sorted[rank - 1] as f64 / 10.0
+}
+
+fn check(results: &BTreeMap<String, Station>, readings: &BTreeMap<String, Vec<i16>>, what: &str) {
+    assert_eq!(results.len(), readings.len(), "{}", what);
+    for (name, values) in readings {
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        let station = &results[name];
+        for percentile in [0.0, 5.0, 50.0, 95.0, 99.0, 99.9, 100.0] {
+            assert_eq!(station.percentile(percentile), Some(nearest_rank(&sorted, percentile)), "p{} of {} {}", percentile, name, what);
+        }
+        let mode = sorted.chunk_by(|a, b| a == b).max_by_key(|run| (run.len(), -run[0])).unwrap()[0];
+        assert_eq!(station.mode(), Some(mode as f64 / 10.0), "mode of {} {}", name, what);
+        assert_eq!(station.histogram().unwrap().count(), values.len() as u64);
+    }
+}
+
+#[test]
+fn percentiles_match_sorting_on_every_input_path() {
+    let (contents, readings) = measurements(4000);
+    let path = input_file("percentiles-twice", &contents);
+    for threads in [1, 3, 8] {
+        let options = Options { threads, fixed_point: true, histograms: true, chunking: Chunking::Queue, chunk_size: 500, block_size: 500, ..Options::default() };
+        for result in aggregate_every_path("percentiles", &contents, &options, 100) {
+            check(&result.unwrap().stations, &readings, &format!("with {:?}", options));
+        }
+
+        let mut twice = readings.clone();
+        twice.values_mut().for_each(|values| values.extend(values.clone()));
+        let report = aggregate_files_report(&[&path, &path], &Options { per_file: true, ..options.clone() }).unwrap();
+        check(&report.stations, &twice, "of two files");
+        check(&report.files[1].stations, &readings, "of the second file");
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn histograms_are_only_kept_when_asked_for() {
+    let (contents, _) = measurements(100);
+    for options in [Options { fixed_point: true, ..Options::default() }, Options { histograms: true, ..Options::default() }] {
+        let results = aggregate_slice(&contents, &options).unwrap();
+        assert!(results.values().all(|station| station.histogram().is_none() && station.percentile(50.0).is_none() && station.mode().is_none()));
+    }
+}
+
+#[test]
+fn merging_adds_the_bins() {
+    let mut histogram = Histogram::default();
+    let mut other = Histogram::default();
+    [-999, -999, 0, 999].into_iter().for_each(|tenths| histogram.add(tenths));
+    [0, 0, 5].into_iter().for_each(|tenths| other.add(tenths));
+    histogram.merge(&other);
+    assert_eq!(histogram.count(), 7);
+    assert_eq!(histogram.mode(), Some(0.0));
+    assert_eq!(histogram.percentile(0.0), Some(-99.9));
+    assert_eq!(histogram.percentile(50.0), Some(0.0));
+    assert_eq!(histogram.percentile(80.0), Some(0.5));
+    assert_eq!(histogram.percentile(100.0), Some(99.9));
+    assert_eq!(Histogram::default().percentile(50.0), None);
+}
+
+#[test]
+fn percentiles_are_written_in_every_format() {
+    let options = Options { fixed_point: true, histograms: true, ..Options::default() };
+    let results = aggregate_slice(b"a;1.0\na;3.0\na;3.0\nb;-2.5\n", &options).unwrap();
+    let written = |format| {
+        let mut out = Vec::new();
+        write_results(&mut out, &results, &OutputOptions { format, percentiles: vec![50.0, 99.9], ..OutputOptions::default() }).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(written(Format::Text), "{a=1.0/2.3/3.0 p50=3.0 p99.9=3.0 mode=3.0, b=-2.5/-2.5/-2.5 p50=-2.5 p99.9=-2.5 mode=-2.5}\n");
+    assert_eq!(written(Format::Json), "{\"a\":{\"min\":1.0,\"mean\":2.3,\"max\":3.0,\"count\":3,\"sum\":7.0,\"p50\":3.0,\"p99.9\":3.0,\"mode\":3.0},\"b\":{\"min\":-2.5,\"mean\":-2.5,\"max\":-2.5,\"count\":1,\"sum\":-2.5,\"p50\":-2.5,\"p99.9\":-2.5,\"mode\":-2.5}}\n");
+    assert_eq!(written(Format::Csv), "station,min,mean,max,count,p50,p99.9,mode\na,1.0,2.3,3.0,3,3.0,3.0,3.0\nb,-2.5,-2.5,-2.5,1,-2.5,-2.5,-2.5\n");
+
+    // without histograms the values are unknown
+    let results = aggregate_slice(b"a;1.0\n", &Options { fixed_point: true, ..Options::default() }).unwrap();
+    let mut out = Vec::new();
+    write_results(&mut out, &results, &OutputOptions { format: Format::Json, percentiles: vec![50.0], ..OutputOptions::default() }).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "{\"a\":{\"min\":1.0,\"mean\":1.0,\"max\":1.0,\"count\":1,\"sum\":1.0,\"p50\":null,\"mode\":null}}\n");
+}
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, fs};
+
+use common::{aggregate_every_path, input_file};
+use onebrc::{aggregate_files_report, aggregate_reader_report, aggregate_reader_summary, aggregate_report, aggregate_slice, Chunking, Error, Options, TDigest};
+
+mod common;
+
+/// A deterministic xorshift generator, for uniform numbers in `0..1`.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Generated readings with four decimals: uniform, roughly normal, skewed and with many repeats.
+fn distributions(count: usize) -> Vec<(&'static str, Vec<f64>)> {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+    let round = |value: f64| (value * 1e4).round() / 1e4;
+    let mut generate = |f: &dyn Fn(&mut Random) -> f64| (0..count).map(|_| round(f(&mut random))).collect::<Vec<_>>();
+    vec![
+        ("uniform", generate(&|random| random.next() * 200.0 - 100.0)),
+        ("normal", generate(&|random| (0..12).map(|_| random.next()).sum::<f64>() * 5.0 - 30.0 + 12.3456)),
+        ("skewed", generate(&|random| -(1.0 - random.next()).ln() * 8.0)),
+        ("repeated", generate(&|random| (random.next() * 5.0).floor() * 2.5)),
+    ]
+}
+
+/// How far `percentile` is from the ranks `estimate` has among the sorted `values`, as a fraction.
+///
+/// Readings within a thousandth of the range of `sorted` count as equal to the estimate, which
+/// may fall just short of a frequent reading.
+fn rank_error(sorted: &[f64], percentile: f64, estimate: f64) -> f64 {
+    let slack = (sorted[sorted.len() - 1] - sorted[0]) * 1e-3;
+    let below = sorted.partition_point(|value| *value < estimate - slack) as f64 / sorted.len() as f64;
+    let at_or_below = sorted.partition_point(|value| *value <= estimate + slack) as f64 / sorted.len() as f64;
+    let quantile = percentile / 100.0;
+    if quantile < below {
+        below - quantile
+    } else {
+        (quantile - at_or_below).max(0.0)
+    }
+}
+
+const PERCENTILES: [f64; 9] = [0.0, 0.1, 1.0, 5.0, 25.0, 50.0, 95.0, 99.0, 100.0];
+
+/// The largest rank error of the percentiles of `digest`.
+fn max_rank_error(digest: &TDigest, values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    PERCENTILES.iter().map(|percentile| rank_error(&sorted, *percentile, digest.percentile(*percentile).unwrap())).fold(0.0, f64::max)
+}
+
+/// The largest rank error we accept from a digest of the given compression.
+fn tolerance(compression: f64) -> f64 {
+    3.0 / compression
+}
+
+#[test]
+fn percentiles_are_close_to_sorting() {
+    for (name, values) in distributions(100_000) {
+        let mut previous = None;
+        for compression in [20.0, 100.0, 500.0] {
+            let mut digest = TDigest::new(compression);
+            values.iter().for_each(|value| digest.add(*value));
+            assert_eq!(digest.count(), values.len() as u64);
+            let error = max_rank_error(&digest, &values);
+            assert!(error <= tolerance(compression), "{} with compression {}: rank error {}", name, compression, error);
+
+            // more accuracy costs more centroids
+            let centroids = digest.centroids();
+            assert!(centroids as f64 <= 2.0 * compression, "{} with compression {}: {} centroids", name, compression, centroids);
+            if let Some((previous_error, previous_centroids)) = previous {
+                assert!(error <= previous_error && centroids >= previous_centroids, "{} with compression {}", name, compression);
+            }
+            previous = Some((error, centroids));
+        }
+    }
+}
+
+#[test]
+fn merged_digests_are_close_to_sorting() {
+    for (name, values) in distributions(20_000) {
+        for parts in [2, 7, 100, 20_000] {
+            let digests: Vec<_> = values
+                .chunks(values.len().div_ceil(parts))
+                .map(|part| {
+                    let mut digest = TDigest::new(100.0);
+                    part.iter().for_each(|value| digest.add(*value));
+                    digest
+                })
+                .collect();
+            // in order, and pairwise like the reduction of the worker results
+            let mut merged = TDigest::new(100.0);
+            digests.iter().for_each(|digest| merged.merge(digest));
+            let mut level = digests;
+            while level.len() > 1 {
+                level = level.chunks(2).map(|pair| pair.iter().fold(TDigest::new(100.0), |mut merged, digest| { merged.merge(digest); merged })).collect();
+            }
+            for digest in [&merged, &level[0]] {
+                assert_eq!(digest.count(), values.len() as u64);
+                let error = max_rank_error(digest, &values);
+                assert!(error <= tolerance(100.0), "{} in {} parts: rank error {}", name, parts, error);
+            }
+        }
+    }
+}
+
+#[test]
+fn few_readings_are_exact() {
+    let mut digest = TDigest::new(100.0);
+    assert_eq!(digest.percentile(50.0), None);
+    digest.add(7.25);
+    assert_eq!([0.0, 50.0, 100.0].map(|percentile| digest.percentile(percentile)), [Some(7.25); 3]);
+    [1.0, 2.0, 3.0, 4.0].into_iter().for_each(|value| digest.add(value));
+    assert_eq!([0.0, 50.0, 100.0].map(|percentile| digest.percentile(percentile)), [Some(1.0), Some(3.0), Some(7.25)]);
+}
+
+#[test]
+fn stations_keep_sketches_on_every_input_path() {
+    let mut readings: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut contents = String::new();
+    for (name, values) in distributions(3000) {
+        for value in &values {
+            contents += &format!("{};{:.4}\n", name, value);
+        }
+        readings.insert(name.to_owned(), values);
+    }
+    for threads in [1, 3, 8] {
+        let options = Options { threads, sketch: Some(100.0), chunking: Chunking::Queue, chunk_size: 1000, block_size: 1000, ..Options::default() };
+        for result in aggregate_every_path("sketch", contents.as_bytes(), &options, 300) {
+            let report = result.unwrap();
+            for (name, values) in &readings {
+                let station = &report.stations[name];
+                let sketch = station.sketch().unwrap();
+                assert_eq!(sketch.count(), station.count());
+                assert_eq!(station.percentile(50.0), sketch.percentile(50.0));
+                let error = max_rank_error(sketch, values);
+                assert!(error <= tolerance(100.0), "{} with {:?}: rank error {}", name, options, error);
+            }
+        }
+    }
+}
+
+#[test]
+fn invalid_compressions_are_an_error() {
+    let contents = b"a;1.0\nb;2.5\n";
+    let path = input_file("sketch-invalid", contents);
+    for compression in [0.5, -1.0, f64::NAN, f64::INFINITY] {
+        let options = Options { sketch: Some(compression), ..Options::default() };
+        let results = [
+            aggregate_report(&path, &options).map(|_| ()),
+            aggregate_slice(contents, &options).map(|_| ()),
+            aggregate_reader_report(contents.as_slice(), &options).map(|_| ()),
+            aggregate_reader_summary(contents.as_slice(), &options).map(|_| ()),
+            aggregate_files_report(&[&path, &path], &options).map(|_| ()),
+        ];
+        for result in results {
+            match result {
+                Err(err @ Error::InvalidOption(_)) => assert_eq!(err.to_string(), "invalid option: the sketch compression must be a finite number of at least 1"),
+                other => panic!("expected an invalid option with compression {}, got {:?}", compression, other),
+            }
+        }
+    }
+    // huge compressions only cost memory up to the number of readings
+    let results = aggregate_slice(contents, &Options { sketch: Some(1e300), ..Options::default() }).unwrap();
+    assert_eq!(results["b"].percentile(50.0), Some(2.5));
+    fs::remove_file(path).unwrap();
+}
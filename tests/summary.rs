@@ -0,0 +1,145 @@
+use std::fs;
+
+use common::{aggregate_every_path, input_file};
+use onebrc::{aggregate_files_report, aggregate_reader_report, aggregate_reader_summary, aggregate_report, write_summary, Chunking, Error, ErrorMode, Format, HyperLogLog, Options, OutputOptions, Rounding, Summary};
+
+mod common;
+
+/// Names whose lengths in characters and bytes disagree, with ties for the longest and shortest.
+const NAMES: [&str; 7] = ["Zürich", "Lagos", "Ürümqi", "Baku", "Oslo", "Abidjan", "Münster"];
+
+/// A header line, then readings of every name and a malformed line now and then.
+fn measurements(lines: usize) -> Vec<u8> {
+    let mut contents = b"station;temperature\n".to_vec();
+    for i in 0..lines {
+        if i % 500 == 499 {
+            contents.extend_from_slice(b"malformed\n");
+        }
+        let tenths = ((i * 7919) % 1999) as i64 - 999;
+        contents.extend_from_slice(format!("{};{}{}.{}\n", NAMES[i % NAMES.len()], if tenths < 0 { "-" } else { "" }, tenths.abs() / 10, tenths.abs() % 10).as_bytes());
+    }
+    contents
+}
+
+fn assert_summary(summary: &Summary, expected: &Summary, what: &str) {
+    let fields = |summary: &Summary| {
+        let overall = &summary.overall;
+        (summary.rows, summary.bytes, summary.stations, summary.longest.clone(), summary.shortest.clone(), overall.min(), overall.max(), overall.count(), overall.rounded_mean(Rounding::Reference), summary.skipped)
+    };
+    assert_eq!(fields(summary), fields(expected), "{}", what);
+}
+
+#[test]
+fn summary_is_the_same_on_every_input_path() {
+    let contents = measurements(5000);
+    let header = "station;temperature\n".len() as u64;
+    let path = input_file("summary", &contents);
+    let frames: Vec<u8> = contents.chunks(1000).flat_map(|frame| zstd::encode_all(frame, 1).unwrap()).collect();
+    let compressed = input_file("summary-zstd", &frames);
+    let middle = contents.len() / 2 + contents[contents.len() / 2..].iter().position(|byte| *byte == b'\n').unwrap() + 1;
+    let (first, second) = contents.split_at(middle);
+    let second = [b"station;temperature\n", second].concat();
+    let halves = [input_file("summary-first", first), input_file("summary-second", &second)];
+
+    let expected = Summary::from(&aggregate_report(&path, &Options { threads: 1, skip_header: 1, errors: ErrorMode::Lenient, ..Options::default() }).unwrap());
+    assert_eq!(expected.rows, 5000);
+    assert_eq!(expected.bytes, contents.len() as u64 - header);
+    assert_eq!(expected.stations, NAMES.len() as u64);
+    assert!(!expected.estimated);
+    assert_eq!(expected.longest.as_deref(), Some("Abidjan"));
+    assert_eq!(expected.shortest.as_deref(), Some("Baku"));
+    assert_eq!(expected.skipped.total(), 10);
+
+    for threads in [1, 3, 8] {
+        let options = Options { threads, skip_header: 1, errors: ErrorMode::Lenient, chunking: Chunking::Queue, chunk_size: 700, block_size: 700, ..Options::default() };
+        for options in [options.clone(), Options { fixed_point: true, ..options.clone() }] {
+            let mut reports = aggregate_every_path("summary-paths", &contents, &options, 300);
+            reports.push(aggregate_files_report(&halves, &options));
+            for report in reports {
+                assert_summary(&Summary::from(&report.unwrap()), &expected, &format!("with {:?}", options));
+            }
+
+            // without a header, every frame of a zstd file is decoded on its own
+            let report = aggregate_report(&compressed, &Options { skip_header: 0, ..options.clone() }).unwrap();
+            let summary = Summary::from(&report);
+            assert_eq!(summary.bytes, contents.len() as u64, "zstd with {:?}", options);
+            assert_eq!(summary.skipped.total(), expected.skipped.total() + 1, "zstd with {:?}", options);
+
+            let summary = aggregate_reader_summary(contents.as_slice(), &options).unwrap();
+            assert!(summary.estimated);
+            assert_summary(&summary, &expected, &format!("estimated with {:?}", options));
+        }
+    }
+    fs::remove_file(path).unwrap();
+    fs::remove_file(compressed).unwrap();
+    for path in halves {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn distinct_estimates_are_within_the_standard_error() {
+    for precision in [10, 14] {
+        let error = 1.04 / ((1 << precision) as f64).sqrt();
+        for distinct in [10, 1000, 100_000] {
+            let mut whole = HyperLogLog::new(precision);
+            let mut halves = [HyperLogLog::new(precision), HyperLogLog::new(precision)];
+            // every name twice, and the halves overlap
+            for i in 0..2 * distinct {
+                let name = format!("Station {}", i % distinct);
+                whole.add(name.as_bytes());
+                halves[usize::from(i % 3 == 0)].add(name.as_bytes());
+            }
+            let estimate = whole.estimate();
+            assert!((estimate / distinct as f64 - 1.0).abs() <= 3.0 * error, "{} names with precision {}: estimated {}", distinct, precision, estimate);
+
+            let [mut merged, other] = halves;
+            merged.merge(&other);
+            assert_eq!(merged, whole);
+        }
+    }
+}
+
+#[test]
+fn distinct_precision_out_of_range_is_an_error() {
+    let contents = b"Ab;1.0\nXy;7.0\n";
+    for distinct_precision in [0, 3, 19, u8::MAX] {
+        match aggregate_reader_summary(contents.as_slice(), &Options { distinct_precision, ..Options::default() }) {
+            Err(err @ Error::InvalidOption(_)) => assert_eq!(err.to_string(), "invalid option: the distinct precision must be from 4 to 18"),
+            other => panic!("expected an invalid option with precision {}, got {:?}", distinct_precision, other),
+        }
+    }
+    for distinct_precision in [4, 18] {
+        let summary = aggregate_reader_summary(contents.as_slice(), &Options { distinct_precision, ..Options::default() }).unwrap();
+        assert_eq!((summary.rows, summary.estimated), (2, true));
+    }
+}
+
+#[test]
+fn summary_is_written_in_every_format() {
+    let contents = b"Ab;1.0\nAbc;3.0\nAb;-2.5\nXy;7.0\n";
+    let summary = Summary::from(&aggregate_reader_report(contents.as_slice(), &Options::default()).unwrap());
+    let written = |summary: &Summary, format| {
+        let mut out = Vec::new();
+        write_summary(&mut out, summary, &OutputOptions { format, stddev: true, ..OutputOptions::default() }).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(written(&summary, Format::Text), "rows: 4\nbytes: 30\nstations: 3\nlongest: Abc\nshortest: Ab\nmin: -2.5\nmean: 2.1\nmax: 7.0\nstddev: 3.4\n");
+    assert_eq!(
+        written(&summary, Format::Json),
+        "{\"rows\":4,\"bytes\":30,\"stations\":3,\"estimated\":false,\"longest\":\"Abc\",\"shortest\":\"Ab\",\"min\":-2.5,\"mean\":2.1,\"max\":7.0,\"stddev\":3.4}\n"
+    );
+    assert_eq!(written(&summary, Format::Csv), "rows,bytes,stations,estimated,longest,shortest,min,mean,max,stddev\n4,30,3,false,Abc,Ab,-2.5,2.1,7.0,3.4\n");
+
+    let estimated = aggregate_reader_summary(contents.as_slice(), &Options::default()).unwrap();
+    assert!(written(&estimated, Format::Text).starts_with("rows: 4\nbytes: 30\nstations: ~3\n"));
+
+    // without readings, the statistics are unknown
+    let empty = Summary::from(&aggregate_reader_report(b"".as_slice(), &Options::default()).unwrap());
+    assert_eq!(written(&empty, Format::Text), "rows: 0\nbytes: 0\nstations: 0\n");
+    assert_eq!(
+        written(&empty, Format::Json),
+        "{\"rows\":0,\"bytes\":0,\"stations\":0,\"estimated\":false,\"longest\":null,\"shortest\":null,\"min\":null,\"mean\":null,\"max\":null,\"stddev\":null}\n"
+    );
+    assert_eq!(written(&empty, Format::Tsv), "rows\tbytes\tstations\testimated\tlongest\tshortest\tmin\tmean\tmax\tstddev\n0\t0\t0\tfalse\t\t\t\t\t\t\n");
+}
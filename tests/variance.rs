@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use onebrc::{aggregate_slice, write_results, Chunking, Format, Options, OutputOptions, Station};
+
+/// Population variance of `values`, computed in two passes.
+fn two_pass_variance(values: &[f64]) -> f64 {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / values.len() as f64
+}
+
+/// Readings of a few stations, in the order they appear in `contents`.
+fn measurements(lines: usize, offset: f64, decimals: usize) -> (Vec<u8>, BTreeMap<String, Vec<f64>>) {
+    let mut contents = String::new();
+    let mut readings: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for i in 0..lines {
+        let name = format!("Station{}", i % 7);
+        let value = format!("{:.*}", decimals, offset + ((i * 7919) % 1999) as f64 / 10.0 - 99.9 + (i % 3) as f64 * 0.0123);
+        contents += &format!("{};{}\n", name, value);
+        readings.entry(name).or_default().push(value.parse().unwrap());
+    }
+    (contents.into_bytes(), readings)
+}
+
+fn assert_close(actual: f64, expected: f64, what: &str) {
+    assert!((actual - expected).abs() <= 1e-9 * expected.abs().max(1.0), "{}: {} != {}", what, actual, expected);
+}
+
+#[test]
+fn variance_matches_two_passes_for_every_split() {
+    let inputs = [(measurements(5000, 0.0, 1), true), (measurements(5000, 0.0, 4), false), (measurements(3000, 1e7, 4), false)];
+    for ((contents, readings), fixed_point) in inputs {
+        for threads in [1, 3, 8] {
+            let options = Options { threads, fixed_point, chunking: Chunking::Queue, chunk_size: 1000, ..Options::default() };
+            let results = aggregate_slice(&contents, &options).unwrap();
+            for (name, values) in &readings {
+                let station = &results[name];
+                assert_close(station.variance(), two_pass_variance(values), name);
+                assert_close(station.stddev(), two_pass_variance(values).sqrt(), name);
+            }
+        }
+    }
+}
+
+#[test]
+fn merging_keeps_the_variance() {
+    let values: Vec<f64> = (0..1000).map(|i| 1e8 + ((i * 37) % 101) as f64 * 0.25).collect();
+    let expected = two_pass_variance(&values);
+    for parts in [1, 2, 7, 1000] {
+        let mut merged = Station::default();
+        // also merges empty stations
+        merged.merge(Station::default());
+        for part in values.chunks(values.len().div_ceil(parts)) {
+            let mut station = Station::default();
+            part.iter().for_each(|value| station.update(*value));
+            merged.merge(station);
+        }
+        assert_eq!(merged.count(), values.len() as u64);
+        assert_close(merged.variance(), expected, &format!("{} parts", parts));
+    }
+}
+
+#[test]
+fn stddev_is_written_in_every_format() {
+    let results = aggregate_slice(b"a;1.0\na;3.0\nb;-2.5\n", &Options { fixed_point: true, ..Options::default() }).unwrap();
+    let written = |format| {
+        let mut out = Vec::new();
+        write_results(&mut out, &results, &OutputOptions { format, stddev: true, ..OutputOptions::default() }).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(written(Format::Text), "{a=1.0/2.0/3.0/1.0, b=-2.5/-2.5/-2.5/0.0}\n");
+    assert_eq!(written(Format::Json), "{\"a\":{\"min\":1.0,\"mean\":2.0,\"max\":3.0,\"count\":2,\"sum\":4.0,\"stddev\":1.0},\"b\":{\"min\":-2.5,\"mean\":-2.5,\"max\":-2.5,\"count\":1,\"sum\":-2.5,\"stddev\":0.0}}\n");
+    assert_eq!(written(Format::Csv), "station,min,mean,max,count,stddev\na,1.0,2.0,3.0,2,1.0\nb,-2.5,-2.5,-2.5,1,0.0\n");
+    assert_eq!(written(Format::Tsv), "station\tmin\tmean\tmax\tcount\tstddev\na\t1.0\t2.0\t3.0\t2\t1.0\nb\t-2.5\t-2.5\t-2.5\t1\t0.0\n");
+}